@@ -0,0 +1,149 @@
+use anyhow::*;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub nick: String,
+    /// Argon2 hash in PHC string format, the salt is embedded in it.
+    pub password_hash: String,
+    pub created_at: u64,
+    /// Masks of the nicks the account ignores.
+    #[serde(default)]
+    pub ignores: Vec<String>,
+}
+impl Account {
+    pub fn new(nick: String, password: &str) -> Result<Self> {
+        Ok(Account {
+            nick,
+            password_hash: hash_password(password)?,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            ignores: Vec::new(),
+        })
+    }
+    pub fn verify_password(&self, password: &str) -> bool {
+        let Result::Ok(hash) = PasswordHash::new(&self.password_hash) else {
+            return false;
+        };
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    }
+    pub fn set_password(&mut self, password: &str) -> Result<()> {
+        self.password_hash = hash_password(password)?;
+        Ok(())
+    }
+}
+
+fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow!("unable to hash password: {}", e))?;
+    Ok(hash.to_string())
+}
+
+/// File backed account store, nicks are matched case insensitively.
+#[derive(Debug, Clone)]
+pub struct UserStore {
+    path: PathBuf,
+    accounts: HashMap<String, Account>,
+}
+impl UserStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut accounts = HashMap::new();
+        if path.exists() {
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("unable to read user store {}", path.display()))?;
+            let list: Vec<Account> = serde_json::from_str(&content)
+                .with_context(|| format!("unable to parse user store {}", path.display()))?;
+            for account in list {
+                accounts.insert(account.nick.to_lowercase(), account);
+            }
+        }
+        Ok(UserStore { path, accounts })
+    }
+    pub fn save(&self) -> Result<()> {
+        let list = self.accounts();
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(&list)?)
+            .with_context(|| format!("unable to write user store {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+    pub fn is_registered(&self, nick: &str) -> bool {
+        self.accounts.contains_key(&nick.to_lowercase())
+    }
+    pub fn get_account(&self, nick: &str) -> Option<&Account> {
+        self.accounts.get(&nick.to_lowercase())
+    }
+    pub fn register(&mut self, nick: &str, password: &str) -> Result<()> {
+        if self.is_registered(nick) {
+            bail!("{} is already registered", nick);
+        }
+        let account = Account::new(nick.to_string(), password)?;
+        self.accounts.insert(nick.to_lowercase(), account);
+        self.save()
+    }
+    /// Returns the registered spelling of the nick when the password matches.
+    pub fn verify(&self, nick: &str, password: &str) -> Option<String> {
+        self.get_account(nick)
+            .filter(|account| account.verify_password(password))
+            .map(|account| account.nick.clone())
+    }
+    pub fn change_password(&mut self, nick: &str, old: &str, new: &str) -> Result<()> {
+        let account = self
+            .accounts
+            .get_mut(&nick.to_lowercase())
+            .with_context(|| format!("{} is not registered", nick))?;
+        if !account.verify_password(old) {
+            bail!("wrong password");
+        }
+        account.set_password(new)?;
+        self.save()
+    }
+    pub fn set_ignores(&mut self, nick: &str, ignores: Vec<String>) -> Result<()> {
+        let account = self
+            .accounts
+            .get_mut(&nick.to_lowercase())
+            .with_context(|| format!("{} is not registered", nick))?;
+        account.ignores = ignores;
+        self.save()
+    }
+    pub fn accounts(&self) -> Vec<Account> {
+        let mut list = self.accounts.values().cloned().collect::<Vec<Account>>();
+        list.sort_by(|a, b| a.nick.cmp(&b.nick));
+        list
+    }
+    /// Adds the accounts that are not in the store yet, returns how many were added.
+    pub fn restore(&mut self, accounts: Vec<Account>) -> Result<usize> {
+        let mut restored = 0;
+        for account in accounts {
+            if self.is_registered(&account.nick) {
+                continue;
+            }
+            self.accounts.insert(account.nick.to_lowercase(), account);
+            restored += 1;
+        }
+        if restored > 0 {
+            self.save()?;
+        }
+        Ok(restored)
+    }
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+}
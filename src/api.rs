@@ -0,0 +1,375 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use std::{collections::BTreeSet, fmt, io::Read};
+
+use crate::{
+    commands::args::ArgKind,
+    defs::{unhighlighted, ArcMut, Channel, ChatMessage, MessageKind, ServerEngine},
+};
+
+/// Largest request body accepted, in bytes.
+const MAX_BODY: u64 = 64 * 1024;
+
+#[derive(Serialize)]
+struct ClientInfo {
+    id: usize,
+    nick: String,
+    account: Option<String>,
+    peer: String,
+    channel: String,
+    away: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ChannelInfo {
+    id: usize,
+    name: String,
+    users: usize,
+    persistent: bool,
+    topic: Option<String>,
+    ops: BTreeSet<String>,
+    bans: BTreeSet<String>,
+    slow_mode: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct PostMessage {
+    text: String,
+    nick: Option<String>,
+    #[serde(default)]
+    kind: MessageKind,
+}
+
+#[derive(Deserialize)]
+struct SetTopic {
+    topic: String,
+}
+
+#[derive(Deserialize)]
+struct SetSlowMode {
+    /// Seconds between two messages of a user, `null` or 0 disables slow mode.
+    interval: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct Kick {
+    nick: String,
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Ban {
+    nick: String,
+}
+
+#[derive(Deserialize)]
+struct Op {
+    account: String,
+}
+
+#[derive(Deserialize)]
+struct IpBan {
+    cidr: String,
+    reason: Option<String>,
+}
+
+/// Error answered with `404 Not Found` instead of `400 Bad Request`.
+#[derive(Debug)]
+struct NotFound(String);
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for NotFound {}
+
+/// Serves the admin API on the configured address until the process exits.
+pub fn handle_api(server: ServerEngine) -> Result<()> {
+    let config = server.config.lock().unwrap().api.clone();
+    if config.token.is_empty() {
+        bail!("api.token must be set to enable the admin API");
+    }
+    let http = Server::http(&config.bind)
+        .map_err(|e| anyhow!("unable to bind admin API listener {}: {}", config.bind, e))?;
+    log::info!("admin API listening on {}", config.bind);
+    for mut request in http.incoming_requests() {
+        let peer = request
+            .remote_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+        let token = server.config.lock().unwrap().api.token.clone();
+        let (status, body) = match is_authorized(&request, &token) {
+            false => {
+                log::warn!(peer = peer; "unauthorized admin API request {}", request.url());
+                (401, json!({ "error": "missing or invalid bearer token" }))
+            }
+            true => match route(&server, &mut request) {
+                Result::Ok(body) => (200, body),
+                Err(e) => {
+                    let status = match e.downcast_ref::<NotFound>() {
+                        Some(_) => 404,
+                        None => 400,
+                    };
+                    (
+                        status,
+                        json!({ "error": unhighlighted(&format!("{:#}", e)) }),
+                    )
+                }
+            },
+        };
+        log::info!(peer = peer, status = status; "admin API {} {}", request.method(), request.url());
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header("Content-Type: application/json".parse::<Header>().unwrap());
+        if let Err(e) = request.respond(response) {
+            log::warn!("unable to send admin API response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn is_authorized(request: &Request, token: &str) -> bool {
+    let expected = format!("Bearer {}", token);
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .is_some_and(|header| constant_time_eq(header.value.as_str(), &expected))
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn route(server: &ServerEngine, request: &mut Request) -> Result<Value> {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let path = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(percent_decode)
+        .collect::<Result<Vec<String>>>()?;
+    let path = path.iter().map(String::as_str).collect::<Vec<&str>>();
+    let method = request.method().clone();
+    match (&method, path.as_slice()) {
+        (Method::Get, ["clients"]) => list_clients(server),
+        (Method::Get, ["channels"]) => list_channels(server),
+        (Method::Get, ["channels", name, "history"]) => history(server, name, query),
+        (Method::Post, ["channels", name, "messages"]) => {
+            let body: PostMessage = read_body(request)?;
+            let channel = get_channel(server, name)?;
+            let nick = match body.nick {
+                Some(nick) => check_bot_nick(server, nick)?,
+                None => default_bot_nick(server)?,
+            };
+            server.publish(&channel, ChatMessage::message(body.kind, nick, body.text))?;
+            Ok(json!({ "ok": true }))
+        }
+        (Method::Put, ["channels", name, "topic"]) => {
+            let body: SetTopic = read_body(request)?;
+            let channel = get_channel(server, name)?;
+            let nick = default_bot_nick(server)?;
+            server.set_topic(&channel, &nick, body.topic)?;
+            Ok(json!({ "ok": true }))
+        }
+        (Method::Put, ["channels", name, "slow"]) => {
+            let body: SetSlowMode = read_body(request)?;
+            let channel = get_channel(server, name)?;
+            let nick = default_bot_nick(server)?;
+            let slow_mode = body.interval.filter(|seconds| *seconds > 0);
+            server.set_slow_mode(&channel, &nick, slow_mode)?;
+            Ok(json!({ "ok": true }))
+        }
+        (Method::Post, ["channels", name, "bans"]) => {
+            let body: Ban = read_body(request)?;
+            get_channel(server, name)?;
+            server.ban(name, &body.nick)?;
+            Ok(json!({ "ok": true }))
+        }
+        (Method::Delete, ["channels", name, "bans", nick]) => {
+            get_channel(server, name)?;
+            server.unban(name, nick)?;
+            Ok(json!({ "ok": true }))
+        }
+        (Method::Post, ["channels", name, "ops"]) => {
+            let body: Op = read_body(request)?;
+            get_channel(server, name)?;
+            server.op(name, &body.account)?;
+            Ok(json!({ "ok": true }))
+        }
+        (Method::Delete, ["channels", name, "ops", account]) => {
+            get_channel(server, name)?;
+            server.deop(name, account)?;
+            Ok(json!({ "ok": true }))
+        }
+        (Method::Post, ["kick"]) => {
+            let body: Kick = read_body(request)?;
+            if server
+                .clients
+                .lock()
+                .unwrap()
+                .get_client_by_nick(&body.nick)
+                .is_none()
+            {
+                return Err(NotFound(format!("{} is not connected", body.nick)).into());
+            }
+            let reason = body
+                .reason
+                .unwrap_or_else(|| "Kicked by the server operator".to_string());
+            server.kick(&body.nick, &reason)?;
+            Ok(json!({ "ok": true }))
+        }
+        (Method::Get, ["ipbans"]) => {
+            let bans = server.ip_bans.lock().unwrap().bans();
+            Ok(serde_json::to_value(bans)?)
+        }
+        (Method::Post, ["ipbans"]) => {
+            let body: IpBan = read_body(request)?;
+            let reason = body
+                .reason
+                .unwrap_or_else(|| "Banned by the server operator".to_string());
+            let kicked = server.ip_ban(&body.cidr, &reason)?;
+            Ok(json!({ "ok": true, "disconnected": kicked }))
+        }
+        (Method::Delete, ["ipbans"]) => {
+            let body: IpBan = read_body(request)?;
+            server.ip_unban(&body.cidr)?;
+            Ok(json!({ "ok": true }))
+        }
+        (Method::Post, ["reload"]) => {
+            let path = server.reload_config()?;
+            Ok(json!({ "ok": true, "path": path }))
+        }
+        _ => Err(NotFound(format!("no route for {} {}", method, url)).into()),
+    }
+}
+
+/// Decodes the `%XX` escapes of a URL path segment, `#` is sent as `%23`.
+fn percent_decode(segment: &str) -> Result<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            decoded.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let byte = segment
+            .get(i + 1..i + 3)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .with_context(|| format!("invalid escape in path segment {}", segment))?;
+        decoded.push(byte);
+        i += 3;
+    }
+    String::from_utf8(decoded).with_context(|| format!("path segment {} is not UTF-8", segment))
+}
+
+/// Fails when the nick is not one `/nick` accepts or belongs to a user, bots must not speak
+/// for them.
+fn check_bot_nick(server: &ServerEngine, nick: String) -> Result<String> {
+    ArgKind::Nick.check(nick.clone())?;
+    let connected = server
+        .clients
+        .lock()
+        .unwrap()
+        .get_client_by_nick(&nick)
+        .is_some();
+    let registered = server.users.lock().unwrap().is_registered(&nick);
+    if connected || registered {
+        bail!("{} belongs to a user, pick another bot nick", nick);
+    }
+    Ok(nick)
+}
+
+/// The configured `bot_nick`, checked like the nicks sent with a request since a user may
+/// have taken it since.
+fn default_bot_nick(server: &ServerEngine) -> Result<String> {
+    let nick = server.config.lock().unwrap().api.bot_nick.clone();
+    check_bot_nick(server, nick).context("api.bot_nick cannot be used")
+}
+
+fn list_clients(server: &ServerEngine) -> Result<Value> {
+    let clients = server.clients.lock().unwrap().list.clone();
+    let clients = clients
+        .iter()
+        .map(|client| {
+            let client = client.lock().unwrap();
+            let channel = client.channel.lock().unwrap().name.clone();
+            ClientInfo {
+                id: client.id,
+                nick: client.nick.clone(),
+                account: client.account.clone(),
+                peer: client.peer.to_string(),
+                channel,
+                away: client.away.as_ref().map(|away| away.message.clone()),
+            }
+        })
+        .collect::<Vec<ClientInfo>>();
+    Ok(serde_json::to_value(clients)?)
+}
+
+fn list_channels(server: &ServerEngine) -> Result<Value> {
+    let channels = server.channels.lock().unwrap().list.clone();
+    let channels = channels
+        .iter()
+        .map(|channel| {
+            let channel = channel.lock().unwrap();
+            ChannelInfo {
+                id: channel.id,
+                name: channel.name.clone(),
+                users: channel.subscribers.len(),
+                persistent: channel.persistent,
+                topic: channel.topic.clone(),
+                ops: channel.ops.clone(),
+                bans: channel.bans.clone(),
+                slow_mode: channel.slow_mode,
+            }
+        })
+        .collect::<Vec<ChannelInfo>>();
+    Ok(serde_json::to_value(channels)?)
+}
+
+/// Last messages kept in memory for the channel, `?limit=n` returns only the newest `n`.
+fn history(server: &ServerEngine, name: &str, query: &str) -> Result<Value> {
+    let channel = get_channel(server, name)?;
+    let limit = match query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("limit="))
+    {
+        Some(limit) => limit
+            .parse::<usize>()
+            .with_context(|| format!("invalid limit {}", limit))?,
+        None => usize::MAX,
+    };
+    let history = channel.lock().unwrap().history.clone();
+    let skip = history.len().saturating_sub(limit);
+    let entries = history.into_iter().skip(skip).collect::<Vec<ChatMessage>>();
+    Ok(serde_json::to_value(entries)?)
+}
+
+fn get_channel(server: &ServerEngine, name: &str) -> Result<ArcMut<Channel>> {
+    server
+        .channels
+        .lock()
+        .unwrap()
+        .get_channel(name)
+        .ok_or_else(|| NotFound(format!("channel {} not found", name)).into())
+}
+
+fn read_body<T: for<'de> Deserialize<'de>>(request: &mut Request) -> Result<T> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY)
+        .read_to_string(&mut body)
+        .context("unable to read request body")?;
+    serde_json::from_str(&body).context("invalid JSON body")
+}
@@ -0,0 +1,262 @@
+use anyhow::*;
+use log::Level;
+
+use super::{
+    args::{ArgKind, ArgSpec, Args},
+    memo, Category, Command, CommandContext, CommandRegistry, Permission,
+};
+use crate::{
+    defs::{anonymous_nick, highlighted, is_anonymous_nick, ChatMessage, ANONYMOUS_PREFIX},
+    logger::session_log,
+};
+
+pub fn register(registry: &mut CommandRegistry) {
+    registry.register(Nick);
+    registry.register(Register);
+    registry.register(Login);
+    registry.register(Passwd);
+}
+
+fn check_password_length(ctx: &CommandContext, password: &str) -> Result<()> {
+    let min_length = ctx
+        .server
+        .config
+        .lock()
+        .unwrap()
+        .accounts
+        .min_password_length;
+    if password.chars().count() < min_length {
+        bail!("password must be at least {} characters long", min_length);
+    }
+    Ok(())
+}
+
+struct Nick;
+impl Command for Nick {
+    fn name(&self) -> &'static str {
+        "nick"
+    }
+    fn description(&self) -> &'static str {
+        "changes your nickname"
+    }
+    fn category(&self) -> Category {
+        Category::Account
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/nick alice"]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const { &[ArgSpec::required("nick", ArgKind::Nick, "nickname to use")] }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let nick = args.text("nick")?;
+        // another client may be given that nick on connection or when renamed
+        let id = ctx.client.lock().unwrap().id;
+        if is_anonymous_nick(nick) && !nick.eq_ignore_ascii_case(&anonymous_nick(id)) {
+            bail!(
+                "{} nicks are given by the server, pick another one",
+                highlighted(format!("{}*", ANONYMOUS_PREFIX))
+            );
+        }
+        let in_use = ctx.server.clients.lock().unwrap().get_client_by_nick(nick);
+        if in_use.is_some_and(|other| other != *ctx.client) {
+            bail!("{} is already in use", highlighted(nick));
+        }
+        session_log!(Level::Info, ctx.client, "nick changed to {}", nick);
+        ctx.server.change_nick(ctx.client, nick.to_string())?;
+        ctx.reply(format!(
+            "Command Success: Nickname changed to {}",
+            highlighted(nick)
+        ))?;
+        let registered = ctx.server.users.lock().unwrap().is_registered(nick);
+        if registered && ctx.client.lock().unwrap().is_identified() {
+            return memo::notify_unread(ctx, nick);
+        }
+        if registered {
+            let grace_period = ctx.server.config.lock().unwrap().accounts.grace_period;
+            ctx.reply(format!(
+                "{} is registered, use {} within {} seconds or you will be renamed",
+                highlighted(nick),
+                highlighted("/login <nick> <password>"),
+                grace_period
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+struct Register;
+impl Command for Register {
+    fn name(&self) -> &'static str {
+        "register"
+    }
+    fn description(&self) -> &'static str {
+        "registers your current nickname"
+    }
+    fn category(&self) -> Category {
+        Category::Account
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/register \"correct horse battery\""]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[ArgSpec::required(
+                "password",
+                ArgKind::Word,
+                "password of the new account",
+            )]
+        }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let password = args.text("password")?;
+        check_password_length(ctx, password)?;
+        if ctx.client.lock().unwrap().account.is_some() {
+            bail!("you are already logged in");
+        }
+        let nick = ctx.nick();
+        if is_anonymous_nick(&nick) {
+            bail!(
+                "{} nicks cannot be registered, pick one with {} first",
+                highlighted(format!("{}*", ANONYMOUS_PREFIX)),
+                highlighted("/nick <nick>")
+            );
+        }
+        ctx.server.users.lock().unwrap().register(&nick, password)?;
+        let ignores = {
+            let mut client = ctx.client.lock().unwrap();
+            client.account = Some(nick.clone());
+            client.ignores.clone()
+        };
+        ctx.server
+            .users
+            .lock()
+            .unwrap()
+            .set_ignores(&nick, ignores)?;
+        session_log!(Level::Info, ctx.client, "account registered");
+        ctx.reply(format!(
+            "Command Success: {} registered and logged in",
+            highlighted(nick)
+        ))
+    }
+}
+
+struct Login;
+impl Command for Login {
+    fn name(&self) -> &'static str {
+        "login"
+    }
+    fn description(&self) -> &'static str {
+        "logs in to a registered nickname"
+    }
+    fn category(&self) -> Category {
+        Category::Account
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/login alice \"correct horse battery\""]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[
+                ArgSpec::required("nick", ArgKind::Nick, "registered nickname"),
+                ArgSpec::required("password", ArgKind::Word, "password of the account"),
+            ]
+        }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let (nick, password) = (args.text("nick")?, args.text("password")?);
+        let verified = ctx.server.users.lock().unwrap().verify(nick, password);
+        let Some(nick) = verified else {
+            session_log!(Level::Warn, ctx.client, "failed login as {}", nick);
+            bail!("invalid nick or password");
+        };
+        let squatter = ctx.server.clients.lock().unwrap().get_client_by_nick(&nick);
+        if let Some(squatter) = squatter.filter(|other| other != ctx.client) {
+            let anonymous = anonymous_nick(squatter.lock().unwrap().id);
+            ctx.server.change_nick(&squatter, anonymous.clone())?;
+            squatter
+                .lock()
+                .unwrap()
+                .rx
+                .push_back(ChatMessage::server(format!(
+                    "{} logged in, you are now known as {}",
+                    highlighted(&nick),
+                    highlighted(anonymous)
+                )))?;
+        }
+        session_log!(Level::Info, ctx.client, "logged in as {}", nick);
+        ctx.server.change_nick(ctx.client, nick.clone())?;
+        let stored = ctx
+            .server
+            .users
+            .lock()
+            .unwrap()
+            .get_account(&nick)
+            .map(|account| account.ignores.clone())
+            .unwrap_or_default();
+        let ignores = {
+            let mut client = ctx.client.lock().unwrap();
+            client.account = Some(nick.clone());
+            // masks ignored before logging in are kept and added to the account
+            for mask in stored.into_iter().rev() {
+                if !client
+                    .ignores
+                    .iter()
+                    .any(|other| other.eq_ignore_ascii_case(&mask))
+                {
+                    client.ignores.insert(0, mask);
+                }
+            }
+            client.ignores.clone()
+        };
+        ctx.server
+            .users
+            .lock()
+            .unwrap()
+            .set_ignores(&nick, ignores)?;
+        ctx.reply(format!(
+            "Command Success: Logged in as {}",
+            highlighted(&nick)
+        ))?;
+        memo::notify_unread(ctx, &nick)
+    }
+}
+
+struct Passwd;
+impl Command for Passwd {
+    fn name(&self) -> &'static str {
+        "passwd"
+    }
+    fn description(&self) -> &'static str {
+        "changes your account password"
+    }
+    fn category(&self) -> Category {
+        Category::Account
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/passwd \"old secret\" \"new secret\""]
+    }
+    fn permission(&self) -> Permission {
+        Permission::LoggedIn
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[
+                ArgSpec::required("old password", ArgKind::Word, "current password"),
+                ArgSpec::required("new password", ArgKind::Word, "password replacing it"),
+            ]
+        }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let account = ctx.client.lock().unwrap().account.clone();
+        let account = account.context("you must be logged in")?;
+        let (old, new) = (args.text("old password")?, args.text("new password")?);
+        check_password_length(ctx, new)?;
+        ctx.server
+            .users
+            .lock()
+            .unwrap()
+            .change_password(&account, old, new)?;
+        ctx.reply("Command Success: Password changed".to_string())
+    }
+}
@@ -0,0 +1,377 @@
+use anyhow::*;
+
+use std::time::Duration;
+
+use crate::defs::highlighted;
+
+/// Longest nick or channel name accepted.
+const MAX_NAME_LENGTH: usize = 32;
+
+/// Type of a command argument, checked when the command line is parsed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgKind {
+    /// A single token of any content.
+    Word,
+    Nick,
+    /// A nick where `*` and `?` match any text and any character.
+    Mask,
+    Channel,
+    Integer,
+    /// `30`, `30s`, `5m`, `2h` or `1d`, `off` is zero.
+    Duration,
+    /// Everything left on the line, as typed.
+    Rest,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub required: bool,
+    /// Shown by `/help <command>`.
+    pub description: &'static str,
+}
+impl ArgSpec {
+    pub const fn required(name: &'static str, kind: ArgKind, description: &'static str) -> Self {
+        ArgSpec {
+            name,
+            kind,
+            required: true,
+            description,
+        }
+    }
+    pub const fn optional(name: &'static str, kind: ArgKind, description: &'static str) -> Self {
+        ArgSpec {
+            name,
+            kind,
+            required: false,
+            description,
+        }
+    }
+    /// `<name>` or `[name]` as shown in the command usage.
+    pub fn usage(&self) -> String {
+        match self.required {
+            true => format!("<{}>", self.name),
+            false => format!("[{}]", self.name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgValue {
+    Text(String),
+    Integer(i64),
+    Duration(Duration),
+}
+
+/// Arguments of a command, by the name of their spec.
+#[derive(Debug, Clone, Default)]
+pub struct Args {
+    values: Vec<(&'static str, ArgValue)>,
+}
+impl Args {
+    /// Parses `line` against `specs`, errors carry the command `usage`.
+    pub fn parse(specs: &[ArgSpec], line: &str, usage: &str) -> Result<Self> {
+        let mut tokenizer = Tokenizer::new(line);
+        let mut values = Vec::new();
+        for spec in specs {
+            let value = match spec.kind {
+                ArgKind::Rest => {
+                    Some(tokenizer.take_rest().to_string()).filter(|rest| !rest.is_empty())
+                }
+                _ => tokenizer.next_token().map_err(|e| usage_error(e, usage))?,
+            };
+            let Some(value) = value else {
+                if spec.required {
+                    bail!("{} is required", highlighted(usage));
+                }
+                break;
+            };
+            let value = spec.kind.check(value).map_err(|e| usage_error(e, usage))?;
+            values.push((spec.name, value));
+        }
+        if !tokenizer.rest().is_empty() {
+            return Err(usage_error(anyhow!("too many arguments"), usage));
+        }
+        Ok(Args { values })
+    }
+    pub fn get(&self, name: &str) -> Option<&ArgValue> {
+        self.values
+            .iter()
+            .find(|(other, _)| *other == name)
+            .map(|(_, value)| value)
+    }
+    pub fn optional_text(&self, name: &str) -> Option<&str> {
+        match self.get(name) {
+            Some(ArgValue::Text(text)) => Some(text),
+            _ => None,
+        }
+    }
+    pub fn text(&self, name: &str) -> Result<&str> {
+        self.optional_text(name)
+            .with_context(|| format!("{} is required", name))
+    }
+    pub fn optional_integer(&self, name: &str) -> Option<i64> {
+        match self.get(name) {
+            Some(ArgValue::Integer(integer)) => Some(*integer),
+            _ => None,
+        }
+    }
+    pub fn optional_duration(&self, name: &str) -> Option<Duration> {
+        match self.get(name) {
+            Some(ArgValue::Duration(duration)) => Some(*duration),
+            _ => None,
+        }
+    }
+}
+
+impl ArgKind {
+    /// Validates a single value, as done for each argument of a command line.
+    pub fn check(&self, value: String) -> Result<ArgValue> {
+        match self {
+            ArgKind::Word | ArgKind::Rest => Ok(ArgValue::Text(value)),
+            ArgKind::Nick => {
+                let valid =
+                    is_valid_name(&value, |c| c.is_alphanumeric() || "-_[]{}|^`".contains(c));
+                ensure!(valid, "{} is not a valid nick", highlighted(value));
+                Ok(ArgValue::Text(value))
+            }
+            ArgKind::Mask => {
+                let valid =
+                    is_valid_name(&value, |c| c.is_alphanumeric() || "-_[]{}|^`*?".contains(c));
+                ensure!(valid, "{} is not a valid nick or mask", highlighted(value));
+                Ok(ArgValue::Text(value))
+            }
+            ArgKind::Channel => {
+                let valid = is_valid_name(&value, |c| c.is_alphanumeric() || "#-_.".contains(c));
+                ensure!(valid, "{} is not a valid channel name", highlighted(value));
+                Ok(ArgValue::Text(value))
+            }
+            ArgKind::Integer => value
+                .parse::<i64>()
+                .map(ArgValue::Integer)
+                .map_err(|_| anyhow!("{} is not a number", highlighted(value))),
+            ArgKind::Duration => parse_duration(&value).map(ArgValue::Duration),
+        }
+    }
+}
+
+fn is_valid_name(name: &str, allowed: impl Fn(char) -> bool) -> bool {
+    !name.is_empty() && name.chars().count() <= MAX_NAME_LENGTH && name.chars().all(allowed)
+}
+
+fn usage_error(error: Error, usage: &str) -> Error {
+    anyhow!("{}, usage: {}", error, highlighted(usage))
+}
+
+/// Parses `30`, `30s`, `5m`, `2h` or `1d`, a bare number is in seconds and `off` is zero.
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    if value.eq_ignore_ascii_case("off") {
+        return Ok(Duration::ZERO);
+    }
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let multiplier = match unit.to_lowercase().as_str() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => bail!("{} is not a valid duration", highlighted(value)),
+    };
+    let seconds = number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .with_context(|| format!("{} is not a valid duration", highlighted(value)))?;
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Splits a command line into whitespace separated tokens.
+///
+/// Double and single quotes group words, a backslash escapes the next character except inside
+/// single quotes. Tokens are read one at a time so the rest of the line can be taken as typed.
+pub struct Tokenizer<'a> {
+    line: &'a str,
+    position: usize,
+}
+impl<'a> Tokenizer<'a> {
+    pub fn new(line: &'a str) -> Self {
+        Tokenizer { line, position: 0 }
+    }
+    /// The unread part of the line without surrounding whitespace.
+    pub fn rest(&self) -> &'a str {
+        self.line[self.position..].trim()
+    }
+    /// Consumes the unread part of the line.
+    pub fn take_rest(&mut self) -> &'a str {
+        let rest = self.rest();
+        self.position = self.line.len();
+        rest
+    }
+    pub fn next_token(&mut self) -> Result<Option<String>> {
+        let rest = &self.line[self.position..];
+        let start = rest.len() - rest.trim_start().len();
+        let mut chars = rest[start..].char_indices().peekable();
+        if chars.peek().is_none() {
+            self.position = self.line.len();
+            return Ok(None);
+        }
+        let mut token = String::new();
+        let mut quote = None;
+        let mut end = rest.len() - start;
+        while let Some((index, c)) = chars.next() {
+            match (quote, c) {
+                (None, c) if c.is_whitespace() => {
+                    end = index;
+                    break;
+                }
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(open), c) if c == open => quote = None,
+                (None | Some('"'), '\\') => match chars.next() {
+                    Some((_, escaped)) => token.push(escaped),
+                    None => token.push('\\'),
+                },
+                (_, c) => token.push(c),
+            }
+        }
+        if let Some(open) = quote {
+            bail!("unterminated {} quote", open);
+        }
+        self.position += start + end;
+        Ok(Some(token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(line: &str) -> Result<Vec<String>> {
+        let mut tokenizer = Tokenizer::new(line);
+        let mut tokens = Vec::new();
+        while let Some(token) = tokenizer.next_token()? {
+            tokens.push(token);
+        }
+        Ok(tokens)
+    }
+
+    #[test]
+    fn tokenizer_splits_on_whitespace() {
+        assert_eq!(tokens("  a  bc\td ").unwrap(), ["a", "bc", "d"]);
+        assert!(tokens("   ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn tokenizer_groups_quoted_words() {
+        assert_eq!(
+            tokens(r#""old secret" 'new secret'"#).unwrap(),
+            ["old secret", "new secret"]
+        );
+        assert_eq!(tokens(r#"a"b c"d"#).unwrap(), ["ab cd"]);
+        assert_eq!(tokens(r#"'say "hi"'"#).unwrap(), [r#"say "hi""#]);
+        assert_eq!(tokens(r#""""#).unwrap(), [""]);
+    }
+
+    #[test]
+    fn tokenizer_handles_escapes() {
+        assert_eq!(tokens(r#"a\ b \"c"#).unwrap(), ["a b", "\"c"]);
+        assert_eq!(tokens(r#""a \" b""#).unwrap(), [r#"a " b"#]);
+        // no escapes inside single quotes
+        assert_eq!(tokens(r"'a\b'").unwrap(), [r"a\b"]);
+        assert_eq!(tokens(r"trailing\").unwrap(), [r"trailing\"]);
+    }
+
+    #[test]
+    fn tokenizer_rejects_unterminated_quotes() {
+        let error = tokens(r#"a "b c"#).unwrap_err();
+        assert_eq!(error.to_string(), "unterminated \" quote");
+        let error = tokens("'b c").unwrap_err();
+        assert_eq!(error.to_string(), "unterminated ' quote");
+    }
+
+    #[test]
+    fn tokenizer_keeps_the_rest_as_typed() {
+        let mut tokenizer = Tokenizer::new("bob  hello   \"world\" ");
+        assert_eq!(tokenizer.next_token().unwrap().as_deref(), Some("bob"));
+        assert_eq!(tokenizer.take_rest(), "hello   \"world\"");
+        assert_eq!(tokenizer.next_token().unwrap(), None);
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("2H").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+        assert!(parse_duration("5w").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("-5m").is_err());
+        assert!(parse_duration("").is_err());
+    }
+
+    #[test]
+    fn parse_duration_off() {
+        assert_eq!(parse_duration("off").unwrap(), Duration::ZERO);
+        assert_eq!(parse_duration("OFF").unwrap(), Duration::ZERO);
+    }
+
+    #[test]
+    fn parse_duration_overflow() {
+        assert!(parse_duration("99999999999999999999").is_err());
+        assert!(parse_duration(&format!("{}d", u64::MAX / 86400 + 1)).is_err());
+        assert_eq!(
+            parse_duration(&format!("{}d", u64::MAX / 86400)).unwrap(),
+            Duration::from_secs(u64::MAX / 86400 * 86400)
+        );
+    }
+
+    #[test]
+    fn check_names() {
+        assert!(ArgKind::Nick.check("bob[away]".to_string()).is_ok());
+        assert!(ArgKind::Nick.check("bob*".to_string()).is_err());
+        assert!(ArgKind::Nick.check(String::new()).is_err());
+        assert!(ArgKind::Nick.check("a".repeat(MAX_NAME_LENGTH)).is_ok());
+        assert!(ArgKind::Nick
+            .check("a".repeat(MAX_NAME_LENGTH + 1))
+            .is_err());
+        assert!(ArgKind::Mask.check("bob*?".to_string()).is_ok());
+        assert!(ArgKind::Mask.check("bob#".to_string()).is_err());
+        assert!(ArgKind::Channel.check("#rust-lang.fr".to_string()).is_ok());
+        assert!(ArgKind::Channel.check("rust lang".to_string()).is_err());
+    }
+
+    #[test]
+    fn check_values() {
+        assert_eq!(
+            ArgKind::Word.check("a b".to_string()).unwrap(),
+            ArgValue::Text("a b".to_string())
+        );
+        assert_eq!(
+            ArgKind::Integer.check("-12".to_string()).unwrap(),
+            ArgValue::Integer(-12)
+        );
+        assert!(ArgKind::Integer.check("12a".to_string()).is_err());
+        assert_eq!(
+            ArgKind::Duration.check("2m".to_string()).unwrap(),
+            ArgValue::Duration(Duration::from_secs(120))
+        );
+    }
+
+    #[test]
+    fn parse_against_specs() {
+        const SPECS: &[ArgSpec] = &[
+            ArgSpec::required("nick", ArgKind::Nick, ""),
+            ArgSpec::optional("text", ArgKind::Rest, ""),
+        ];
+        let args = Args::parse(SPECS, "bob  hello  there", "/msg").unwrap();
+        assert_eq!(args.text("nick").unwrap(), "bob");
+        assert_eq!(args.text("text").unwrap(), "hello  there");
+        let args = Args::parse(SPECS, "bob", "/msg").unwrap();
+        assert_eq!(args.optional_text("text"), None);
+        assert!(Args::parse(SPECS, "", "/msg").is_err());
+        assert!(Args::parse(&SPECS[..1], "bob extra", "/msg").is_err());
+    }
+}
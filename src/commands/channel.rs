@@ -0,0 +1,310 @@
+use anyhow::*;
+use log::Level;
+
+use std::time::Duration;
+
+use super::{
+    args::{ArgKind, ArgSpec, Args},
+    Category, Command, CommandContext, CommandRegistry,
+};
+use crate::{defs::highlighted, format_duration, logger::session_log};
+
+pub fn register(registry: &mut CommandRegistry) {
+    registry.register(Join);
+    registry.register(Topic);
+    registry.register(Slow);
+    registry.register(Who);
+    registry.register(Names);
+    registry.register(List);
+}
+
+struct Join;
+impl Command for Join {
+    fn name(&self) -> &'static str {
+        "join"
+    }
+    fn description(&self) -> &'static str {
+        "joins a channel, creating it if needed"
+    }
+    fn category(&self) -> Category {
+        Category::Channel
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/join rust"]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[ArgSpec::required(
+                "channel",
+                ArgKind::Channel,
+                "channel to join",
+            )]
+        }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let name = args.text("channel")?;
+        let existing = ctx.server.channels.lock().unwrap().get_channel(name);
+        let nick = ctx.nick();
+        if existing.is_some_and(|channel| channel.lock().unwrap().is_banned(&nick)) {
+            bail!("you are banned from {}", highlighted(name));
+        }
+        let channel = ctx.server.open_channel(name)?;
+        ctx.server.move_client(ctx.client, channel.clone())?;
+        session_log!(Level::Info, ctx.client, "joined {}", name);
+
+        let account = ctx.account();
+        let topic = {
+            let mut channel = channel.lock().unwrap();
+            if let Some(account) = account {
+                if channel.ops.is_empty() && !channel.persistent {
+                    channel.ops.insert(account.to_lowercase());
+                }
+            }
+            channel.topic.clone()
+        };
+        ctx.reply(format!("Command Success: Joined {}", highlighted(name)))?;
+        if let Some(topic) = topic {
+            ctx.reply(format!("Topic: {}", topic))?;
+        }
+        Ok(())
+    }
+}
+
+struct Topic;
+impl Command for Topic {
+    fn name(&self) -> &'static str {
+        "topic"
+    }
+    fn description(&self) -> &'static str {
+        "shows the channel topic, operators can change it"
+    }
+    fn category(&self) -> Category {
+        Category::Channel
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/topic", "/topic Rust 2024 release party"]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[ArgSpec::optional(
+                "topic",
+                ArgKind::Rest,
+                "new topic of the channel",
+            )]
+        }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let channel = ctx.client.lock().unwrap().channel.clone();
+        let Some(topic) = args.optional_text("topic") else {
+            let topic = channel.lock().unwrap().topic.clone();
+            return match topic {
+                Some(topic) => ctx.reply(format!("Topic: {}", topic)),
+                None => ctx.reply("Topic: no topic is set".to_string()),
+            };
+        };
+        let nick = ctx.nick();
+        if !channel.lock().unwrap().is_op(ctx.account().as_deref()) {
+            bail!("only channel operators can set the topic");
+        }
+        ctx.server.set_topic(&channel, &nick, topic.to_string())
+    }
+}
+
+struct Slow;
+impl Command for Slow {
+    fn name(&self) -> &'static str {
+        "slow"
+    }
+    fn description(&self) -> &'static str {
+        "shows the channel slow mode, operators can change it"
+    }
+    fn category(&self) -> Category {
+        Category::Channel
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/slow 30s", "/slow off"]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[ArgSpec::optional(
+                "interval|off",
+                ArgKind::Duration,
+                "time between two messages of a user, off disables slow mode",
+            )]
+        }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let channel = ctx.client.lock().unwrap().channel.clone();
+        let Some(interval) = args.optional_duration("interval|off") else {
+            let slow_mode = channel.lock().unwrap().slow_mode;
+            return match slow_mode {
+                Some(seconds) => {
+                    ctx.reply(format!("Slow mode: one message every {} seconds", seconds))
+                }
+                None => ctx.reply("Slow mode: off".to_string()),
+            };
+        };
+        let slow_mode = Some(interval.as_secs()).filter(|seconds| *seconds > 0);
+        let (nick, account) = (ctx.nick(), ctx.account());
+        if !channel.lock().unwrap().is_op(account.as_deref()) {
+            bail!("only channel operators can set slow mode");
+        }
+        ctx.server.set_slow_mode(&channel, &nick, slow_mode)
+    }
+}
+
+struct Who;
+impl Command for Who {
+    fn name(&self) -> &'static str {
+        "who"
+    }
+    fn description(&self) -> &'static str {
+        "lists the users in a channel with their idle time and away message"
+    }
+    fn category(&self) -> Category {
+        Category::Channel
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/who", "/who rust"]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[ArgSpec::optional(
+                "channel",
+                ArgKind::Channel,
+                "channel to list, your channel by default",
+            )]
+        }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let (name, members) = channel_members(ctx, args.optional_text("channel"))?;
+        ctx.reply(format!(
+            "Who: {} users in {}",
+            members.len(),
+            highlighted(name)
+        ))?;
+        for member in members {
+            let mut line = format!(
+                "{} idle {}",
+                highlighted(member.nick),
+                format_duration(member.idle)
+            );
+            if let Some(message) = member.away {
+                line += &format!(", away: {}", message);
+            }
+            ctx.reply(line)?;
+        }
+        Ok(())
+    }
+}
+
+struct Names;
+impl Command for Names {
+    fn name(&self) -> &'static str {
+        "names"
+    }
+    fn description(&self) -> &'static str {
+        "lists the nicknames in a channel"
+    }
+    fn category(&self) -> Category {
+        Category::Channel
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/names rust"]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[ArgSpec::optional(
+                "channel",
+                ArgKind::Channel,
+                "channel to list, your channel by default",
+            )]
+        }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let (name, members) = channel_members(ctx, args.optional_text("channel"))?;
+        let names = members
+            .iter()
+            .map(|member| match member.away {
+                Some(_) => format!("{} (away)", highlighted(&member.nick)),
+                None => highlighted(&member.nick),
+            })
+            .collect::<Vec<String>>();
+        ctx.reply_list(&format!("Names {}", highlighted(name)), &names, " ")
+    }
+}
+
+struct Member {
+    /// Nick prefixed with `@` for channel operators.
+    nick: String,
+    away: Option<String>,
+    idle: Duration,
+}
+
+/// Returns the channel name with its members, defaults to the client's channel.
+fn channel_members(ctx: &CommandContext, name: Option<&str>) -> Result<(String, Vec<Member>)> {
+    let channel = match name {
+        Some(name) => ctx
+            .server
+            .channels
+            .lock()
+            .unwrap()
+            .get_channel(name)
+            .with_context(|| format!("channel {} not found", highlighted(name)))?,
+        None => ctx.client.lock().unwrap().channel.clone(),
+    };
+    let (name, ops) = {
+        let channel = channel.lock().unwrap();
+        (channel.name.clone(), channel.ops.clone())
+    };
+    let members = ctx
+        .server
+        .channel_members(&channel)
+        .iter()
+        .map(|member| {
+            let member = member.lock().unwrap();
+            let is_op = member
+                .account
+                .as_ref()
+                .is_some_and(|account| ops.contains(&account.to_lowercase()));
+            let prefix = match is_op {
+                true => "@",
+                false => "",
+            };
+            Member {
+                nick: format!("{}{}", prefix, member.nick),
+                away: member.away.as_ref().map(|away| away.message.clone()),
+                idle: member.last_activity.elapsed(),
+            }
+        })
+        .collect();
+    Ok((name, members))
+}
+
+struct List;
+impl Command for List {
+    fn name(&self) -> &'static str {
+        "list"
+    }
+    fn description(&self) -> &'static str {
+        "lists the open channels"
+    }
+    fn category(&self) -> Category {
+        Category::Channel
+    }
+    fn run(&self, ctx: &CommandContext, _args: Args) -> Result<()> {
+        let channels = ctx.server.channels.lock().unwrap().list.clone();
+        for channel in channels {
+            let (name, subscribers) = {
+                let channel = channel.lock().unwrap();
+                (channel.name.clone(), channel.subscribers.len())
+            };
+            ctx.reply(format!(
+                "channel: {} users: {}",
+                highlighted(name),
+                highlighted(subscribers.to_string())
+            ))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+use anyhow::*;
+
+use super::{
+    args::{ArgKind, ArgSpec, Args},
+    Category, Command, CommandContext, CommandRegistry, Permission,
+};
+use crate::{
+    defs::{highlighted, mask_matches, ArcMut, ChatMessage},
+    memos::{Memo, MemoStore},
+};
+
+pub fn register(registry: &mut CommandRegistry) {
+    registry.register(MemoCommand);
+}
+
+/// Characters of a memo shown by `/memo list`.
+const PREVIEW_LENGTH: usize = 40;
+
+/// Memos per `/memo list` page, a memo can take two lines of the default 20 line chat.
+const PAGE_SIZE: usize = 8;
+
+struct MemoCommand;
+impl Command for MemoCommand {
+    fn name(&self) -> &'static str {
+        "memo"
+    }
+    fn description(&self) -> &'static str {
+        "leaves notes for registered users, read when they log in"
+    }
+    fn category(&self) -> Category {
+        Category::Messaging
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &[
+            "/memo send bob the build is fixed",
+            "/memo list",
+            "/memo list 2",
+            "/memo read 2",
+            "/memo del all",
+        ]
+    }
+    fn permission(&self) -> Permission {
+        Permission::LoggedIn
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[
+                ArgSpec::required("action", ArgKind::Word, "send, list, read or del"),
+                ArgSpec::optional(
+                    "arguments",
+                    ArgKind::Rest,
+                    "nick and text for send, page for list, memo number for read, number or all for del",
+                ),
+            ]
+        }
+    }
+    fn usage(&self) -> String {
+        "/memo send <nick> <text> | list [page] | read [number] | del <number|all>".to_string()
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let account = ctx.client.lock().unwrap().account.clone();
+        let account = account.context("you must be logged in")?;
+        let line = args.optional_text("arguments").unwrap_or("");
+        match args.text("action")?.to_lowercase().as_str() {
+            "send" => send(ctx, &account, line),
+            "list" => list(ctx, &account, line),
+            "read" => read(ctx, &account, line),
+            "del" => delete(ctx, &account, line),
+            action => bail!(
+                "{} is not a memo action, usage: {}",
+                highlighted(action),
+                highlighted(self.usage())
+            ),
+        }
+    }
+}
+
+fn send(ctx: &CommandContext, account: &str, line: &str) -> Result<()> {
+    let args = Args::parse(
+        const {
+            &[
+                ArgSpec::required("nick", ArgKind::Nick, "registered user"),
+                ArgSpec::required("text", ArgKind::Rest, "text of the memo"),
+            ]
+        },
+        line,
+        "/memo send <nick> <text>",
+    )?;
+    let (nick, text) = (args.text("nick")?, args.text("text")?);
+    let recipient = ctx
+        .server
+        .users
+        .lock()
+        .unwrap()
+        .get_account(nick)
+        .map(|account| (account.nick.clone(), account.ignores.clone()));
+    let (recipient, ignores) = recipient.with_context(|| {
+        format!(
+            "{} is not registered, memos can only be left to registered users",
+            highlighted(nick)
+        )
+    })?;
+    let reply = format!("Command Success: Memo sent to {}", highlighted(&recipient));
+    if ignores.iter().any(|mask| mask_matches(mask, account)) {
+        // the sender is not told, like an ignored private message
+        return ctx.reply(reply);
+    }
+    let config = ctx.server.config.lock().unwrap().memos.clone();
+    memos(ctx)?
+        .lock()
+        .unwrap()
+        .send(account, &recipient, text, &config)?;
+    let clients = ctx.server.clients.lock().unwrap().list.clone();
+    for client in clients {
+        let mut client = client.lock().unwrap();
+        if client
+            .account
+            .as_ref()
+            .is_some_and(|other| other.eq_ignore_ascii_case(&recipient))
+        {
+            client.rx.push_back(ChatMessage::server(format!(
+                "New memo from {}, type {} to read it",
+                highlighted(account),
+                highlighted("/memo read")
+            )))?;
+        }
+    }
+    ctx.reply(reply)
+}
+
+fn list(ctx: &CommandContext, account: &str, line: &str) -> Result<()> {
+    let args = Args::parse(
+        const { &[ArgSpec::optional("page", ArgKind::Integer, "page to show")] },
+        line,
+        "/memo list [page]",
+    )?;
+    let inbox = memos(ctx)?
+        .lock()
+        .unwrap()
+        .inbox(account)
+        .into_iter()
+        .cloned()
+        .collect::<Vec<Memo>>();
+    if inbox.is_empty() {
+        return ctx.reply("Memos: you have no memos".to_string());
+    }
+    let pages = inbox.len().div_ceil(PAGE_SIZE);
+    let page = args.optional_integer("page").unwrap_or(1);
+    let page = usize::try_from(page)
+        .ok()
+        .filter(|page| (1..=pages).contains(page))
+        .with_context(|| format!("there is no memo page {}", page))?;
+    let unread = inbox.iter().filter(|memo| !memo.read).count();
+    ctx.reply(format!(
+        "Memos: {} memos, {} unread, page {}/{}",
+        inbox.len(),
+        unread,
+        page,
+        pages
+    ))?;
+    let start = (page - 1) * PAGE_SIZE;
+    for (position, memo) in inbox.iter().enumerate().skip(start).take(PAGE_SIZE) {
+        let mut preview = memo.text.chars().take(PREVIEW_LENGTH).collect::<String>();
+        if memo.text.chars().count() > PREVIEW_LENGTH {
+            preview += "...";
+        }
+        let status = match memo.read {
+            true => "",
+            false => " (new)",
+        };
+        ctx.reply(format!(
+            "{}{} {} from {}: {}",
+            position + 1,
+            status,
+            memo.sent_at.format("%Y-%m-%d %H:%M"),
+            highlighted(&memo.from),
+            preview
+        ))?;
+    }
+    if page < pages {
+        ctx.reply(format!(
+            "Memos: type {} for more",
+            highlighted(format!("/memo list {}", page + 1))
+        ))?;
+    }
+    Ok(())
+}
+
+fn read(ctx: &CommandContext, account: &str, line: &str) -> Result<()> {
+    let args = Args::parse(
+        const {
+            &[ArgSpec::optional(
+                "number",
+                ArgKind::Integer,
+                "memo to read",
+            )]
+        },
+        line,
+        "/memo read [number]",
+    )?;
+    let number = args
+        .optional_integer("number")
+        .map(memo_number)
+        .transpose()?;
+    let read = memos(ctx)?.lock().unwrap().read(account, number)?;
+    if read.is_empty() {
+        return ctx.reply(format!(
+            "Memos: no unread memos, type {} to see them all",
+            highlighted("/memo list")
+        ));
+    }
+    for (number, memo) in read {
+        ctx.reply(format!(
+            "Memo {} from {}, {}: {}",
+            number,
+            highlighted(memo.from),
+            memo.sent_at.format("%Y-%m-%d %H:%M"),
+            memo.text
+        ))?;
+    }
+    Ok(())
+}
+
+fn delete(ctx: &CommandContext, account: &str, line: &str) -> Result<()> {
+    let args = Args::parse(
+        const {
+            &[ArgSpec::required(
+                "number|all",
+                ArgKind::Word,
+                "memo to delete",
+            )]
+        },
+        line,
+        "/memo del <number|all>",
+    )?;
+    let target = args.text("number|all")?;
+    let number = match target.eq_ignore_ascii_case("all") {
+        true => None,
+        false => {
+            let number = target
+                .parse::<i64>()
+                .map_err(|_| anyhow!("{} is not a number", highlighted(target)))?;
+            Some(memo_number(number)?)
+        }
+    };
+    let deleted = memos(ctx)?.lock().unwrap().delete(account, number)?;
+    ctx.reply(format!("Command Success: {} memos deleted", deleted))
+}
+
+fn memo_number(number: i64) -> Result<usize> {
+    usize::try_from(number)
+        .ok()
+        .filter(|number| *number > 0)
+        .with_context(|| format!("there is no memo number {}", number))
+}
+
+fn memos<'a>(ctx: &CommandContext<'a>) -> Result<&'a ArcMut<MemoStore>> {
+    ctx.server.memos.as_ref().context("memos are disabled")
+}
+
+/// Tells the client logged in to `account` how many memos are waiting for it.
+pub fn notify_unread(ctx: &CommandContext, account: &str) -> Result<()> {
+    let Some(memos) = &ctx.server.memos else {
+        return Ok(());
+    };
+    let unread = memos.lock().unwrap().unread(account);
+    if unread == 0 {
+        return Ok(());
+    }
+    ctx.reply(format!(
+        "You have {} unread memos, type {} to read them",
+        unread,
+        highlighted("/memo read")
+    ))
+}
@@ -0,0 +1,443 @@
+use anyhow::*;
+
+use super::{
+    args::{ArgKind, ArgSpec, Args},
+    Category, Command, CommandContext, CommandRegistry,
+};
+use crate::{
+    defs::{
+        highlighted, mask_matches, ArcMut, Away, Channel, ChatMessage, MessageKind, RenderStyle,
+    },
+    search::{format_record, SearchQuery},
+    welcome_back,
+};
+
+pub fn register(registry: &mut CommandRegistry) {
+    registry.register(Msg);
+    registry.register(Me);
+    registry.register(Notice);
+    registry.register(AwayCommand);
+    registry.register(Back);
+    registry.register(Mentions);
+    registry.register(Ignore);
+    registry.register(Unignore);
+    registry.register(Search);
+}
+
+struct Msg;
+impl Command for Msg {
+    fn name(&self) -> &'static str {
+        "msg"
+    }
+    fn description(&self) -> &'static str {
+        "sends a private message"
+    }
+    fn category(&self) -> Category {
+        Category::Messaging
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/msg bob are you there?"]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[
+                ArgSpec::required("nick", ArgKind::Nick, "user to send the message to"),
+                ArgSpec::required("message", ArgKind::Rest, "text of the message"),
+            ]
+        }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let (nick, message) = (args.text("nick")?, args.text("message")?);
+        let target = ctx.server.clients.lock().unwrap().get_client_by_nick(nick);
+        let target = target.with_context(|| format!("{} is not connected", highlighted(nick)))?;
+        ctx.server
+            .send_private(ctx.client, &target, MessageKind::Chat, message.to_string())?;
+        let away = target
+            .lock()
+            .unwrap()
+            .away
+            .as_ref()
+            .map(|away| away.message.clone());
+        if let Some(away) = away {
+            ctx.reply(format!("{} is away: {}", highlighted(nick), away))?;
+        }
+        Ok(())
+    }
+}
+
+struct Me;
+impl Command for Me {
+    fn name(&self) -> &'static str {
+        "me"
+    }
+    fn description(&self) -> &'static str {
+        "describes what you are doing to your channel"
+    }
+    fn category(&self) -> Category {
+        Category::Messaging
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/me waves"]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[ArgSpec::required(
+                "action",
+                ArgKind::Rest,
+                "what you are doing",
+            )]
+        }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let (channel, nick) = {
+            let client = ctx.client.lock().unwrap();
+            (client.channel.clone(), client.nick.clone())
+        };
+        check_slow_mode(ctx, &channel, &nick)?;
+        let action = args.text("action")?.to_string();
+        ctx.server.publish(
+            &channel,
+            ChatMessage::message(MessageKind::Action, nick, action),
+        )
+    }
+}
+
+/// Fails when the client has to wait before talking in the channel again.
+fn check_slow_mode(ctx: &CommandContext, channel: &ArcMut<Channel>, nick: &str) -> Result<()> {
+    let account = ctx.client.lock().unwrap().account.clone();
+    let wait = channel
+        .lock()
+        .unwrap()
+        .slow_mode_wait(nick, account.as_deref());
+    if let Some(wait) = wait {
+        bail!(
+            "slow mode, wait {} seconds before sending another message",
+            wait
+        );
+    }
+    Ok(())
+}
+
+struct Notice;
+impl Command for Notice {
+    fn name(&self) -> &'static str {
+        "notice"
+    }
+    fn description(&self) -> &'static str {
+        "sends a message to a user or to your #channel that must not be answered automatically"
+    }
+    fn category(&self) -> Category {
+        Category::Messaging
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &[
+            "/notice #rust build 1234 passed",
+            "/notice bob your job finished",
+        ]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[
+                ArgSpec::required("target", ArgKind::Word, "nick, or #channel you are in"),
+                ArgSpec::required("text", ArgKind::Rest, "text of the notice"),
+            ]
+        }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let (target, text) = (args.text("target")?, args.text("text")?.to_string());
+        let Some(name) = target.strip_prefix('#') else {
+            let to = ctx
+                .server
+                .clients
+                .lock()
+                .unwrap()
+                .get_client_by_nick(target);
+            let to = to.with_context(|| format!("{} is not connected", highlighted(target)))?;
+            // no away reply, notices are never answered automatically
+            return ctx
+                .server
+                .send_private(ctx.client, &to, MessageKind::Notice, text);
+        };
+        let (channel, nick) = {
+            let client = ctx.client.lock().unwrap();
+            (client.channel.clone(), client.nick.clone())
+        };
+        let joined = {
+            let channel = channel.lock().unwrap();
+            channel.name == name || channel.name == target
+        };
+        if !joined {
+            bail!("you are not in {}", highlighted(target));
+        }
+        check_slow_mode(ctx, &channel, &nick)?;
+        ctx.server.publish(
+            &channel,
+            ChatMessage::message(MessageKind::Notice, nick, text),
+        )
+    }
+}
+
+struct AwayCommand;
+impl Command for AwayCommand {
+    fn name(&self) -> &'static str {
+        "away"
+    }
+    fn description(&self) -> &'static str {
+        "marks you as away, messages are counted until you are back"
+    }
+    fn category(&self) -> Category {
+        Category::Messaging
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/away lunch, back at 2"]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[ArgSpec::optional(
+                "message",
+                ArgKind::Rest,
+                "message shown to users writing to you",
+            )]
+        }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let message = args.optional_text("message").unwrap_or("away").to_string();
+        {
+            let mut client = ctx.client.lock().unwrap();
+            match client.away.as_mut() {
+                Some(away) => {
+                    away.message = message.clone();
+                    away.automatic = false;
+                }
+                None => client.away = Some(Away::new(message.clone(), false)),
+            }
+        }
+        ctx.reply(format!(
+            "Command Success: You are marked as away: {}",
+            message
+        ))
+    }
+}
+
+struct Back;
+impl Command for Back {
+    fn name(&self) -> &'static str {
+        "back"
+    }
+    fn description(&self) -> &'static str {
+        "clears your away status and shows what you missed"
+    }
+    fn category(&self) -> Category {
+        Category::Messaging
+    }
+    fn run(&self, ctx: &CommandContext, _args: Args) -> Result<()> {
+        let away = ctx.client.lock().unwrap().away.take();
+        let away = away.context("you are not marked as away")?;
+        for line in welcome_back(&away) {
+            ctx.reply(line)?;
+        }
+        Ok(())
+    }
+}
+
+struct Mentions;
+impl Command for Mentions {
+    fn name(&self) -> &'static str {
+        "mentions"
+    }
+    fn description(&self) -> &'static str {
+        "lists the recent messages mentioning you or your highlight words"
+    }
+    fn category(&self) -> Category {
+        Category::Messaging
+    }
+    fn run(&self, ctx: &CommandContext, _args: Args) -> Result<()> {
+        let mentions = ctx.client.lock().unwrap().terminal.chat.mentions.clone();
+        if mentions.is_empty() {
+            return ctx.reply("Mentions: nobody mentioned you".to_string());
+        }
+        ctx.reply(format!("Mentions: {} recent messages", mentions.len()))?;
+        for msg in mentions {
+            let (nick, text) = msg.render(RenderStyle::Plain);
+            ctx.reply(format!(
+                "{} {}{}",
+                msg.timestamp.format("%Y-%m-%d %H:%M"),
+                nick,
+                text
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+/// Most masks a client may ignore.
+const MAX_IGNORES: usize = 50;
+
+struct Ignore;
+impl Command for Ignore {
+    fn name(&self) -> &'static str {
+        "ignore"
+    }
+    fn description(&self) -> &'static str {
+        "hides the messages of a user, or lists the ignored ones"
+    }
+    fn category(&self) -> Category {
+        Category::Messaging
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/ignore bob", "/ignore Anonymous-*", "/ignore list"]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[ArgSpec::optional(
+                "nick|mask|list",
+                ArgKind::Mask,
+                "nick to ignore, * and ? match any text and any character",
+            )]
+        }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let mask = args
+            .optional_text("nick|mask|list")
+            .filter(|mask| !mask.eq_ignore_ascii_case("list"));
+        let Some(mask) = mask else {
+            let ignores = ctx.client.lock().unwrap().ignores.clone();
+            return match ignores.is_empty() {
+                true => ctx.reply("Ignore: you are not ignoring anybody".to_string()),
+                false => {
+                    let ignores = ignores.iter().map(highlighted).collect::<Vec<String>>();
+                    ctx.reply_list("Ignore", &ignores, ", ")
+                }
+            };
+        };
+        if mask_matches(mask, &ctx.nick()) {
+            bail!("you cannot ignore yourself");
+        }
+        {
+            let mut client = ctx.client.lock().unwrap();
+            if client
+                .ignores
+                .iter()
+                .any(|other| other.eq_ignore_ascii_case(mask))
+            {
+                bail!("you are already ignoring {}", highlighted(mask));
+            }
+            if client.ignores.len() >= MAX_IGNORES {
+                bail!("you can ignore at most {} users", MAX_IGNORES);
+            }
+            client.ignores.push(mask.to_string());
+        }
+        save_ignores(ctx)?;
+        ctx.reply(format!("Command Success: Ignoring {}", highlighted(mask)))
+    }
+}
+
+struct Unignore;
+impl Command for Unignore {
+    fn name(&self) -> &'static str {
+        "unignore"
+    }
+    fn description(&self) -> &'static str {
+        "shows the messages of an ignored user again"
+    }
+    fn category(&self) -> Category {
+        Category::Messaging
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/unignore bob"]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[ArgSpec::required(
+                "nick|mask",
+                ArgKind::Mask,
+                "nick or mask given to /ignore",
+            )]
+        }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let mask = args.text("nick|mask")?;
+        {
+            let mut client = ctx.client.lock().unwrap();
+            let index = client
+                .ignores
+                .iter()
+                .position(|other| other.eq_ignore_ascii_case(mask))
+                .with_context(|| format!("you are not ignoring {}", highlighted(mask)))?;
+            client.ignores.remove(index);
+        }
+        save_ignores(ctx)?;
+        ctx.reply(format!(
+            "Command Success: No longer ignoring {}",
+            highlighted(mask)
+        ))
+    }
+}
+
+/// Stores the ignore list in the account the client is logged in to, if any.
+fn save_ignores(ctx: &CommandContext) -> Result<()> {
+    let (account, ignores) = {
+        let client = ctx.client.lock().unwrap();
+        (client.account.clone(), client.ignores.clone())
+    };
+    match account {
+        Some(account) => ctx
+            .server
+            .users
+            .lock()
+            .unwrap()
+            .set_ignores(&account, ignores),
+        None => Ok(()),
+    }
+}
+
+struct Search;
+impl Command for Search {
+    fn name(&self) -> &'static str {
+        "search"
+    }
+    fn description(&self) -> &'static str {
+        "searches the message history"
+    }
+    fn category(&self) -> Category {
+        Category::Messaging
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/search #rust from:alice since:2024-01-01 borrow checker"]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[ArgSpec::required(
+                "terms",
+                ArgKind::Rest,
+                "words to look for, with optional filters",
+            )]
+        }
+    }
+    fn usage(&self) -> String {
+        "/search [#channel] [from:nick] [since:YYYY-MM-DD] [page:n] <terms>".to_string()
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let search = ctx.server.search.as_ref().context("search is disabled")?;
+        let query = SearchQuery::parse(args.text("terms")?)?;
+        let page_size = ctx.server.config.lock().unwrap().search.page_size;
+        let results = search.lock().unwrap().search(&query, page_size);
+        if results.total == 0 {
+            return ctx.reply("Search: no messages found".to_string());
+        }
+        for record in results.records.iter() {
+            ctx.reply(format_record(record))?;
+        }
+        let mut footer = format!(
+            "Search: page {}/{} of {} results",
+            results.page, results.pages, results.total
+        );
+        if results.page < results.pages {
+            footer += &format!(
+                ", add {} for more",
+                highlighted(format!("page:{}", results.page + 1))
+            );
+        }
+        ctx.reply(footer)
+    }
+}
@@ -0,0 +1,323 @@
+mod account;
+pub mod args;
+mod channel;
+mod memo;
+mod message;
+mod settings;
+
+use anyhow::*;
+use log::Level;
+
+use std::{cell::RefCell, fmt};
+
+use self::args::{ArgKind, ArgSpec, Args};
+use crate::{
+    defs::{highlighted, visible_length, ArcMut, ChatMessage, Client, ServerEngine},
+    logger::session_log,
+};
+
+/// Who may run a command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Permission {
+    Anyone,
+    /// Only clients logged in to an account.
+    LoggedIn,
+}
+impl Permission {
+    pub fn allows(&self, client: &Client) -> bool {
+        match self {
+            Permission::Anyone => true,
+            Permission::LoggedIn => client.account.is_some(),
+        }
+    }
+}
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Permission::Anyone => write!(f, "anyone"),
+            Permission::LoggedIn => write!(f, "logged in users"),
+        }
+    }
+}
+
+/// Group a command is listed under in `/help`, in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    Account,
+    Channel,
+    Messaging,
+    General,
+}
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Category::Account => write!(f, "Account"),
+            Category::Channel => write!(f, "Channel"),
+            Category::Messaging => write!(f, "Messaging"),
+            Category::General => write!(f, "General"),
+        }
+    }
+}
+
+/// Longest list reply line, in visible characters.
+const MAX_LIST_LINE: usize = 200;
+
+/// What a command runs with: the server, the invoking client and the registry it was found in.
+pub struct CommandContext<'a> {
+    pub server: &'a ServerEngine,
+    pub client: &'a ArcMut<Client>,
+    pub registry: &'a CommandRegistry,
+    replies: RefCell<Vec<ChatMessage>>,
+}
+impl CommandContext<'_> {
+    /// Adds a server message to the reply, shown to the client once the command is done.
+    pub fn reply(&self, msg: String) -> Result<()> {
+        self.replies.borrow_mut().push(ChatMessage::server(msg));
+        Ok(())
+    }
+    /// Replies with the items separated by `separator`, starting a new `title` line whenever
+    /// a line would grow longer than `MAX_LIST_LINE` characters.
+    pub fn reply_list(&self, title: &str, items: &[String], separator: &str) -> Result<()> {
+        let mut line = String::new();
+        for item in items {
+            let length = visible_length(&line) + visible_length(item);
+            if !line.is_empty() && length + separator.len() > MAX_LIST_LINE {
+                self.reply(format!("{}: {}", title, line))?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line += separator;
+            }
+            line += item;
+        }
+        self.reply(format!("{}: {}", title, line))
+    }
+    pub fn nick(&self) -> String {
+        self.client.lock().unwrap().nick.clone()
+    }
+    /// Account the client is logged in to, if any.
+    pub fn account(&self) -> Option<String> {
+        self.client.lock().unwrap().account.clone()
+    }
+}
+
+/// A slash command. Errors returned by `run` are shown to the client as `Command Error: ...`.
+pub trait Command: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+    fn description(&self) -> &'static str;
+    fn category(&self) -> Category;
+    /// Command lines shown by `/help <command>`.
+    fn examples(&self) -> &'static [&'static str] {
+        &[]
+    }
+    fn permission(&self) -> Permission {
+        Permission::Anyone
+    }
+    /// Arguments in the order they are typed.
+    fn args(&self) -> &'static [ArgSpec] {
+        &[]
+    }
+    /// Syntax shown in `/help` and when the arguments are wrong, e.g. `/nick <nick>`.
+    fn usage(&self) -> String {
+        let mut usage = format!("/{}", self.name());
+        for arg in self.args() {
+            usage += " ";
+            usage += &arg.usage();
+        }
+        usage
+    }
+    fn parse(&self, line: &str) -> Result<Args> {
+        Args::parse(self.args(), line, &self.usage())
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()>;
+}
+
+/// Commands known to the server, looked up by name or alias.
+pub struct CommandRegistry {
+    commands: Vec<Box<dyn Command>>,
+}
+impl CommandRegistry {
+    pub fn new() -> Self {
+        CommandRegistry {
+            commands: Vec::new(),
+        }
+    }
+    /// Registry holding every built-in command.
+    pub fn with_defaults() -> Self {
+        let mut registry = CommandRegistry::new();
+        account::register(&mut registry);
+        channel::register(&mut registry);
+        message::register(&mut registry);
+        memo::register(&mut registry);
+        settings::register(&mut registry);
+        registry.register(Help);
+        registry
+    }
+    pub fn register(&mut self, command: impl Command + 'static) {
+        self.commands.push(Box::new(command));
+    }
+    pub fn find(&self, name: &str) -> Option<&dyn Command> {
+        self.commands
+            .iter()
+            .find(|command| {
+                command.name().eq_ignore_ascii_case(name)
+                    || command
+                        .aliases()
+                        .iter()
+                        .any(|alias| alias.eq_ignore_ascii_case(name))
+            })
+            .map(|command| command.as_ref())
+    }
+    pub fn commands(&self) -> impl Iterator<Item = &dyn Command> {
+        self.commands.iter().map(|command| command.as_ref())
+    }
+    /// Runs the `/command args` line for the client and returns the reply, ending with the error
+    /// if it failed.
+    pub fn dispatch(
+        &self,
+        server: &ServerEngine,
+        client: &ArcMut<Client>,
+        line: &str,
+    ) -> Vec<ChatMessage> {
+        let line = line.trim_start_matches('/');
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        session_log!(Level::Debug, client, "command {}", name);
+        let ctx = CommandContext {
+            server,
+            client,
+            registry: self,
+            replies: RefCell::new(Vec::new()),
+        };
+        let Some(command) = self.find(name) else {
+            server.metrics.count_command("unknown");
+            return vec![ChatMessage::error(format!(
+                "Command Error: {} is not a valid command",
+                highlighted(name)
+            ))];
+        };
+        server.metrics.count_command(command.name());
+        let result = check_permission(command, client)
+            .and_then(|_| command.parse(args))
+            .and_then(|args| command.run(&ctx, args));
+        let mut replies = ctx.replies.into_inner();
+        if let Err(e) = result {
+            replies.push(ChatMessage::error(format!("Command Error: {:#}", e)));
+        }
+        replies
+    }
+}
+impl Default for CommandRegistry {
+    fn default() -> Self {
+        Self::with_defaults()
+    }
+}
+impl fmt::Debug for CommandRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.commands.iter().map(|command| command.name()))
+            .finish()
+    }
+}
+
+fn check_permission(command: &dyn Command, client: &ArcMut<Client>) -> Result<()> {
+    let permission = command.permission();
+    if !permission.allows(&client.lock().unwrap()) {
+        bail!(
+            "{} can only be run by {}",
+            highlighted(command.usage()),
+            permission
+        );
+    }
+    Ok(())
+}
+
+struct Help;
+impl Command for Help {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+    fn description(&self) -> &'static str {
+        "lists the commands you can run, or shows how to use one"
+    }
+    fn category(&self) -> Category {
+        Category::General
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/help", "/help join"]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[ArgSpec::optional(
+                "command",
+                ArgKind::Word,
+                "command to describe, with or without the leading /",
+            )]
+        }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        match args.optional_text("command") {
+            Some(name) => describe(ctx, name),
+            None => list(ctx),
+        }
+    }
+}
+
+/// Lists the commands the client may run by category.
+fn list(ctx: &CommandContext) -> Result<()> {
+    let mut commands = {
+        let client = ctx.client.lock().unwrap();
+        ctx.registry
+            .commands()
+            .filter(|command| command.permission().allows(&client))
+            .collect::<Vec<&dyn Command>>()
+    };
+    commands.sort_by_key(|command| command.category());
+    // one line per category, the whole list has to fit in the chat area
+    for category in commands.chunk_by(|a, b| a.category() == b.category()) {
+        let names = category
+            .iter()
+            .map(|command| highlighted(format!("/{}", command.name())))
+            .collect::<Vec<String>>();
+        ctx.reply_list(&format!("{} commands", category[0].category()), &names, " ")?;
+    }
+    ctx.reply(format!(
+        "Type {} for its usage and details",
+        highlighted("/help <command>")
+    ))
+}
+
+/// Shows the usage, arguments, permission and examples of a command.
+fn describe(ctx: &CommandContext, name: &str) -> Result<()> {
+    let command = ctx
+        .registry
+        .find(name.trim_start_matches('/'))
+        .with_context(|| format!("{} is not a valid command", highlighted(name)))?;
+    ctx.reply(format!(
+        "{} {}",
+        highlighted(command.usage()),
+        command.description()
+    ))?;
+    for arg in command.args() {
+        let required = match arg.required {
+            true => "",
+            false => ", optional",
+        };
+        ctx.reply(format!(
+            "  {}: {}{}",
+            highlighted(arg.name),
+            arg.description,
+            required
+        ))?;
+    }
+    if !command.aliases().is_empty() {
+        ctx.reply(format!("Aliases: {}", command.aliases().join(", ")))?;
+    }
+    ctx.reply(format!("Permission: {}", command.permission()))?;
+    for example in command.examples() {
+        ctx.reply(format!("Example: {}", highlighted(example)))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use anyhow::*;
+
+use super::{
+    args::{ArgKind, ArgSpec, Args},
+    Category, Command, CommandContext, CommandRegistry,
+};
+use crate::defs::{highlighted, Settings};
+
+pub fn register(registry: &mut CommandRegistry) {
+    registry.register(Set);
+}
+
+/// Names of the settings changed with `/set`.
+const SETTINGS: &[&str] = &["joins", "highlights"];
+
+struct Set;
+impl Command for Set {
+    fn name(&self) -> &'static str {
+        "set"
+    }
+    fn description(&self) -> &'static str {
+        "shows or changes your settings"
+    }
+    fn category(&self) -> Category {
+        Category::General
+    }
+    fn examples(&self) -> &'static [&'static str] {
+        &["/set", "/set joins off", "/set highlights rust, release"]
+    }
+    fn args(&self) -> &'static [ArgSpec] {
+        const {
+            &[
+                ArgSpec::optional("setting", ArgKind::Word, "joins or highlights"),
+                ArgSpec::optional(
+                    "value",
+                    ArgKind::Rest,
+                    "on or off for joins, words separated by commas or off for highlights",
+                ),
+            ]
+        }
+    }
+    fn run(&self, ctx: &CommandContext, args: Args) -> Result<()> {
+        let Some(setting) = args.optional_text("setting") else {
+            let settings = ctx.client.lock().unwrap().settings.clone();
+            let values = SETTINGS
+                .iter()
+                .map(|name| format!("{} {}", highlighted(name), value(&settings, name)))
+                .collect::<Vec<String>>();
+            return ctx.reply(format!("Settings: {}", values.join(", ")));
+        };
+        let setting = setting.to_lowercase();
+        if !SETTINGS.contains(&setting.as_str()) {
+            bail!("{} is not a valid setting", highlighted(setting));
+        }
+        let Some(new_value) = args.optional_text("value") else {
+            let settings = ctx.client.lock().unwrap().settings.clone();
+            return ctx.reply(format!(
+                "{}: {}",
+                highlighted(&setting),
+                value(&settings, &setting)
+            ));
+        };
+        let settings = {
+            let mut client = ctx.client.lock().unwrap();
+            match setting.as_str() {
+                "joins" => client.settings.joins = parse_switch(&setting, new_value)?,
+                _ => client.settings.highlights = parse_words(new_value),
+            }
+            client.update_highlights();
+            client.settings.clone()
+        };
+        ctx.reply(format!(
+            "Command Success: {} set to {}",
+            highlighted(&setting),
+            value(&settings, &setting)
+        ))
+    }
+}
+
+fn parse_switch(setting: &str, value: &str) -> Result<bool> {
+    match value.to_lowercase().as_str() {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => bail!("{} must be on or off", highlighted(setting)),
+    }
+}
+
+/// Splits words separated by commas or spaces, `off` clears them.
+fn parse_words(value: &str) -> Vec<String> {
+    if value.eq_ignore_ascii_case("off") {
+        return Vec::new();
+    }
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_string())
+        .collect()
+}
+
+fn value(settings: &Settings, name: &str) -> String {
+    let switch = |enabled: bool| match enabled {
+        true => "on".to_string(),
+        false => "off".to_string(),
+    };
+    match name {
+        "joins" => switch(settings.joins),
+        _ if settings.highlights.is_empty() => "off".to_string(),
+        _ => settings.highlights.join(", "),
+    }
+}
@@ -0,0 +1,320 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+pub const DEFAULT_CONFIG_PATH: &str = "chathole.toml";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// File the configuration was loaded from, used to reload it.
+    #[serde(skip)]
+    pub path: PathBuf,
+    pub server: ServerConfig,
+    pub channels: ChannelsConfig,
+    pub accounts: AccountsConfig,
+    pub storage: StorageConfig,
+    pub search: SearchConfig,
+    pub snapshot: SnapshotConfig,
+    pub log: LogConfig,
+    pub metrics: MetricsConfig,
+    pub api: ApiConfig,
+    pub flood: FloodConfig,
+    pub limits: LimitsConfig,
+    pub idle: IdleConfig,
+    pub memos: MemosConfig,
+}
+impl Config {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut config = match path.exists() {
+            true => {
+                let content = std::fs::read_to_string(path)
+                    .with_context(|| format!("unable to read config file {}", path.display()))?;
+                toml::from_str::<Config>(&content)
+                    .with_context(|| format!("unable to parse config file {}", path.display()))?
+            }
+            false => Config::default(),
+        };
+        config.path = path.to_path_buf();
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub header: String,
+    pub chat_lines: usize,
+    /// Seconds given to clients to disconnect on shutdown before the process exits anyway.
+    pub shutdown_timeout: u64,
+    /// Longest line in bytes a client may send, longer lines are discarded.
+    pub max_line_length: usize,
+}
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: "0.0.0.0:2121".to_string(),
+            header: "Rust Coded IcsBoyX ChatHole server".to_string(),
+            chat_lines: 20,
+            shutdown_timeout: 5,
+            max_line_length: 512,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelsConfig {
+    /// Channel every new client lands in, always persistent.
+    pub default: String,
+    /// Channels created at startup and never removed when empty.
+    pub persistent: Vec<String>,
+}
+impl Default for ChannelsConfig {
+    fn default() -> Self {
+        ChannelsConfig {
+            default: "broadcast".to_string(),
+            persistent: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccountsConfig {
+    pub path: String,
+    /// Seconds an unauthenticated user may keep a registered nick before being renamed.
+    pub grace_period: u64,
+    pub min_password_length: usize,
+}
+impl Default for AccountsConfig {
+    fn default() -> Self {
+        AccountsConfig {
+            path: "users.json".to_string(),
+            grace_period: 60,
+            min_password_length: 6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub enabled: bool,
+    pub path: String,
+    /// Messages kept in memory per channel and replayed to joining clients.
+    pub history_lines: usize,
+}
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            enabled: true,
+            path: "logs".to_string(),
+            history_lines: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchConfig {
+    pub enabled: bool,
+    /// Most recent channel messages kept in the search index.
+    pub max_records: usize,
+    /// Days of chat logs indexed at startup.
+    pub index_days: u64,
+    pub page_size: usize,
+}
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            enabled: true,
+            max_records: 100_000,
+            index_days: 30,
+            page_size: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotConfig {
+    pub enabled: bool,
+    pub path: String,
+    /// Seconds between two periodic snapshots, 0 only saves on shutdown.
+    pub interval: u64,
+}
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        SnapshotConfig {
+            enabled: true,
+            path: "snapshot.json".to_string(),
+            interval: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub level: String,
+    pub format: LogFormat,
+    /// Log file appended to, standard error when not set.
+    pub file: Option<String>,
+    /// Level per module path prefix, e.g. `"chathole::storage" = "debug"`.
+    pub filters: BTreeMap<String, String>,
+}
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+            file: None,
+            filters: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    /// Address of the HTTP listener serving `/metrics`, read at startup only.
+    pub bind: String,
+}
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            enabled: false,
+            bind: "127.0.0.1:9121".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiConfig {
+    pub enabled: bool,
+    /// Address of the admin HTTP listener, read at startup only.
+    pub bind: String,
+    /// Bearer token every request must carry, the API refuses to start without one.
+    pub token: String,
+    /// Nick used for messages posted through the API when the request does not set one.
+    pub bot_nick: String,
+}
+impl Default for ApiConfig {
+    fn default() -> Self {
+        ApiConfig {
+            enabled: false,
+            bind: "127.0.0.1:9122".to_string(),
+            token: String::new(),
+            bot_nick: "bot".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FloodConfig {
+    pub enabled: bool,
+    /// Chat messages a client may send at once.
+    pub burst: u32,
+    /// Chat messages per second regained after a burst.
+    pub rate: f64,
+    pub command_burst: u32,
+    pub command_rate: f64,
+    /// Lines dropped in a row before the client is disconnected.
+    pub max_violations: u32,
+}
+impl Default for FloodConfig {
+    fn default() -> Self {
+        FloodConfig {
+            enabled: true,
+            burst: 10,
+            rate: 2.0,
+            command_burst: 5,
+            command_rate: 1.0,
+            max_violations: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Connected clients at once, further connections are turned away.
+    pub max_clients: usize,
+    /// Connected clients at once from the same address.
+    pub max_per_ip: usize,
+    /// Connections an address may open at once, then regained per second.
+    pub connect_burst: u32,
+    pub connect_rate: f64,
+    /// File the server wide IP ban list is kept in.
+    pub ban_list: String,
+}
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_clients: 256,
+            max_per_ip: 8,
+            connect_burst: 5,
+            connect_rate: 0.5,
+            ban_list: "bans.json".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IdleConfig {
+    /// Seconds without input before a client is marked away, 0 disables it.
+    pub away_after: u64,
+    /// Seconds without input before a client is disconnected, 0 disables it.
+    pub timeout: u64,
+    /// Seconds between two telnet NOPs sent to find out dead connections, 0 disables them.
+    pub keepalive_interval: u64,
+}
+impl Default for IdleConfig {
+    fn default() -> Self {
+        IdleConfig {
+            away_after: 900,
+            timeout: 3600,
+            keepalive_interval: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemosConfig {
+    pub enabled: bool,
+    pub path: String,
+    /// Memos a user may keep, read or not.
+    pub max_per_user: usize,
+    /// Memos a user may have waiting to be read by others.
+    pub max_unread_sent: usize,
+}
+impl Default for MemosConfig {
+    fn default() -> Self {
+        MemosConfig {
+            enabled: true,
+            path: "memos.json".to_string(),
+            max_per_user: 20,
+            max_unread_sent: 10,
+        }
+    }
+}
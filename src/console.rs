@@ -0,0 +1,282 @@
+use anyhow::*;
+use tabled::{Table, Tabled};
+
+use std::io::BufRead;
+
+use crate::{
+    commands::args::{ArgKind, ArgSpec, Args, Tokenizer},
+    defs::{ChatMessage, CmdMessage, ServerEngine},
+};
+
+/// Reads operator commands from stdin and queues them on the service bus.
+pub fn handle_console(server: ServerEngine) -> Result<()> {
+    let stdin = std::io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        server
+            .service_bus
+            .lock()
+            .unwrap()
+            .push_back(CmdMessage::console(line.to_string()))?;
+    }
+    Ok(())
+}
+
+#[derive(Tabled)]
+struct ClientRow {
+    id: usize,
+    nick: String,
+    account: String,
+    channel: String,
+    away: String,
+}
+
+#[derive(Tabled)]
+struct IpBanRow {
+    cidr: String,
+    reason: String,
+}
+
+#[derive(Tabled)]
+struct ChannelRow {
+    id: usize,
+    name: String,
+    users: usize,
+    persistent: bool,
+    topic: String,
+}
+
+/// Operator commands and their arguments, parsed like the users' slash commands.
+const COMMANDS: &[(&str, &[ArgSpec])] = &[
+    ("clients", &[]),
+    ("channels", &[]),
+    (
+        "kick",
+        &[
+            ArgSpec::required("nick", ArgKind::Nick, "user to disconnect"),
+            ArgSpec::optional("reason", ArgKind::Rest, "shown to the user"),
+        ],
+    ),
+    (
+        "wall",
+        &[ArgSpec::required(
+            "message",
+            ArgKind::Rest,
+            "sent to every user",
+        )],
+    ),
+    (
+        "ban",
+        &[
+            ArgSpec::required("channel", ArgKind::Channel, "channel to ban from"),
+            ArgSpec::required("nick", ArgKind::Nick, "user to ban"),
+        ],
+    ),
+    (
+        "unban",
+        &[
+            ArgSpec::required("channel", ArgKind::Channel, "channel to unban from"),
+            ArgSpec::required("nick", ArgKind::Word, "banned nick"),
+        ],
+    ),
+    (
+        "op",
+        &[
+            ArgSpec::required("channel", ArgKind::Channel, "channel to operate"),
+            ArgSpec::required("account", ArgKind::Nick, "registered account"),
+        ],
+    ),
+    (
+        "deop",
+        &[
+            ArgSpec::required("channel", ArgKind::Channel, "channel operated"),
+            ArgSpec::required("account", ArgKind::Word, "operator account"),
+        ],
+    ),
+    (
+        "slow",
+        &[
+            ArgSpec::required("channel", ArgKind::Channel, "channel to slow down"),
+            ArgSpec::required(
+                "interval|off",
+                ArgKind::Duration,
+                "time between two messages of a user, off disables slow mode",
+            ),
+        ],
+    ),
+    (
+        "ipban",
+        &[
+            ArgSpec::required("address|cidr", ArgKind::Word, "address range to ban"),
+            ArgSpec::optional("reason", ArgKind::Rest, "shown to the users disconnected"),
+        ],
+    ),
+    (
+        "ipunban",
+        &[ArgSpec::required(
+            "address|cidr",
+            ArgKind::Word,
+            "banned address range",
+        )],
+    ),
+    ("ipbans", &[]),
+    ("reload", &[]),
+    ("stats", &[]),
+    ("shutdown", &[]),
+    ("help", &[]),
+];
+
+fn usage(name: &str, specs: &[ArgSpec]) -> String {
+    std::iter::once(name.to_string())
+        .chain(specs.iter().map(ArgSpec::usage))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Runs an operator command on the service bus thread, output goes to stdout.
+pub fn run_console_command(server: &ServerEngine, payload: &str) -> Result<()> {
+    let mut tokenizer = Tokenizer::new(payload.trim_start_matches('/'));
+    let name = tokenizer.next_token()?.unwrap_or_default().to_lowercase();
+    let (name, specs) = COMMANDS
+        .iter()
+        .find(|(other, _)| *other == name)
+        .with_context(|| format!("{} is not a valid command, try help", name))?;
+    let args = Args::parse(specs, tokenizer.rest(), &usage(name, specs))?;
+    match *name {
+        "clients" => {
+            let clients = server.clients.lock().unwrap().list.clone();
+            let rows = clients.iter().map(|client| {
+                let client = client.lock().unwrap();
+                let channel = client.channel.lock().unwrap().name.clone();
+                ClientRow {
+                    id: client.id,
+                    nick: client.nick.clone(),
+                    account: client.account.clone().unwrap_or_default(),
+                    channel,
+                    away: client
+                        .away
+                        .as_ref()
+                        .map(|away| away.message.clone())
+                        .unwrap_or_default(),
+                }
+            });
+            println!("{}", Table::new(rows));
+        }
+        "channels" => {
+            let channels = server.channels.lock().unwrap().list.clone();
+            let rows = channels.iter().map(|channel| {
+                let channel = channel.lock().unwrap();
+                ChannelRow {
+                    id: channel.id,
+                    name: channel.name.clone(),
+                    users: channel.subscribers.len(),
+                    persistent: channel.persistent,
+                    topic: channel.topic.clone().unwrap_or_default(),
+                }
+            });
+            println!("{}", Table::new(rows));
+        }
+        "kick" => {
+            let nick = args.text("nick")?;
+            let reason = args
+                .optional_text("reason")
+                .unwrap_or("Kicked by the server operator");
+            server.kick(nick, reason)?;
+            println!("{} kicked", nick);
+        }
+        "wall" => {
+            server.broadcast(ChatMessage::server(args.text("message")?.to_string()))?;
+        }
+        "ban" => {
+            let (channel, nick) = (args.text("channel")?, args.text("nick")?);
+            server.ban(channel, nick)?;
+            println!("{} banned from {}", nick, channel);
+        }
+        "unban" => {
+            let (channel, nick) = (args.text("channel")?, args.text("nick")?);
+            server.unban(channel, nick)?;
+            println!("{} unbanned from {}", nick, channel);
+        }
+        "op" => {
+            let (channel, account) = (args.text("channel")?, args.text("account")?);
+            server.op(channel, account)?;
+            println!("{} is now an operator of {}", account, channel);
+        }
+        "deop" => {
+            let (channel, account) = (args.text("channel")?, args.text("account")?);
+            server.deop(channel, account)?;
+            println!("{} is no longer an operator of {}", account, channel);
+        }
+        "slow" => {
+            let name = args.text("channel")?;
+            let channel = server
+                .channels
+                .lock()
+                .unwrap()
+                .get_channel(name)
+                .with_context(|| format!("channel {} not found", name))?;
+            let interval = args.optional_duration("interval|off").unwrap_or_default();
+            let slow_mode = Some(interval.as_secs()).filter(|seconds| *seconds > 0);
+            server.set_slow_mode(&channel, "The server operator", slow_mode)?;
+            match slow_mode {
+                Some(seconds) => println!("slow mode of {} set to {} seconds", name, seconds),
+                None => println!("slow mode of {} disabled", name),
+            }
+        }
+        "ipban" => {
+            let cidr = args.text("address|cidr")?;
+            let reason = args
+                .optional_text("reason")
+                .unwrap_or("Banned by the server operator");
+            let kicked = server.ip_ban(cidr, reason)?;
+            println!("{} banned", cidr);
+            if !kicked.is_empty() {
+                println!("disconnected: {}", kicked.join(", "));
+            }
+        }
+        "ipunban" => {
+            let cidr = args.text("address|cidr")?;
+            server.ip_unban(cidr)?;
+            println!("{} unbanned", cidr);
+        }
+        "ipbans" => {
+            let bans = server.ip_bans.lock().unwrap().bans();
+            let rows = bans.into_iter().map(|ban| IpBanRow {
+                cidr: ban.cidr,
+                reason: ban.reason,
+            });
+            println!("{}", Table::new(rows));
+        }
+        "reload" => {
+            let path = server.reload_config()?;
+            println!("Configuration reloaded from {}", path.display());
+        }
+        "stats" => {
+            let uptime = server.started_at.elapsed().as_secs();
+            println!(
+                "uptime: {}h {}m {}s",
+                uptime / 3600,
+                uptime / 60 % 60,
+                uptime % 60
+            );
+            println!("clients: {}", server.clients.lock().unwrap().len());
+            println!("channels: {}", server.channels.lock().unwrap().len());
+            println!("accounts: {}", server.users.lock().unwrap().len());
+            if let Some(search) = &server.search {
+                println!("indexed messages: {}", search.lock().unwrap().len());
+            }
+        }
+        "shutdown" => crate::shutdown(server),
+        "help" => {
+            for (name, specs) in COMMANDS {
+                println!("{}", usage(name, specs));
+            }
+        }
+        _ => bail!("{} is not a valid command, try help", name),
+    }
+    Ok(())
+}
@@ -1,108 +1,760 @@
 #![allow(dead_code)]
 use anyhow::*;
+use colored::{Color, Colorize};
 
-use std::{
-    collections::VecDeque,
-    net::TcpStream,
-    ops::Deref,
-    sync::{Arc, Mutex, RwLock},
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    accounts::UserStore,
+    commands::CommandRegistry,
+    config::Config,
+    limits::IpBanList,
+    memos::MemoStore,
+    metrics::Metrics,
+    search::SearchIndex,
+    snapshot::{ChannelSnapshot, Snapshot},
+    storage::{private_target, ChatStore, LogRecord},
+    terminal_ansi::{formatted_terminal, strip_ansi},
 };
 
-enum Commands {
-    Nick,
-    Join,
-}
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fmt,
+    io::Write,
+    net::{SocketAddr, TcpStream},
+    ops::{Deref, Range},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
 #[derive(Debug, Clone)]
 pub struct ServerEngine {
+    pub config: ArcMut<Config>,
     pub clients: ArcMut<ClientList>,
     pub channels: ArcMut<ChannelList>,
+    pub users: ArcMut<UserStore>,
+    pub ip_bans: ArcMut<IpBanList>,
+    pub storage: Option<ArcMut<ChatStore>>,
+    pub search: Option<ArcMut<SearchIndex>>,
+    pub memos: Option<ArcMut<MemoStore>>,
     pub service_bus: ArcMut<MessageBus<CmdMessage>>,
+    pub metrics: Arc<Metrics>,
+    pub commands: Arc<CommandRegistry>,
+    pub started_at: Instant,
+    shutting_down: Arc<AtomicBool>,
 }
 impl ServerEngine {
-    pub fn new() -> Self {
-        ServerEngine {
-            clients: ArcMut::new(ClientList { list: Vec::new() }),
-            channels: ArcMut::new(ChannelList::new()),
+    pub fn new(config: Config) -> Result<Self> {
+        let channels = ChannelList::from_config(&config);
+        let users = UserStore::open(&config.accounts.path)?;
+        let ip_bans = IpBanList::open(&config.limits.ban_list)?;
+        let storage = match config.storage.enabled {
+            true => Some(ArcMut::new(ChatStore::open(&config.storage.path)?)),
+            false => None,
+        };
+        let search = match config.search.enabled {
+            true => Some(ArcMut::new(SearchIndex::new(config.search.max_records))),
+            false => None,
+        };
+        let memos = match config.memos.enabled {
+            true => Some(ArcMut::new(MemoStore::open(&config.memos.path)?)),
+            false => None,
+        };
+        let index_days = config.search.index_days;
+        let snapshot = match config.snapshot.enabled {
+            true => Snapshot::load(&config.snapshot.path)?,
+            false => None,
+        };
+        let server_engine = ServerEngine {
+            config: ArcMut::new(config),
+            clients: ArcMut::new(ClientList::new()),
+            channels: ArcMut::new(channels),
+            users: ArcMut::new(users),
+            ip_bans: ArcMut::new(ip_bans),
+            storage,
+            search,
+            memos,
             service_bus: ArcMut::new(MessageBus::new()),
+            metrics: Arc::new(Metrics::new()),
+            commands: Arc::new(CommandRegistry::with_defaults()),
+            started_at: Instant::now(),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+        };
+        if let Some(snapshot) = snapshot {
+            server_engine.restore(snapshot)?;
+        }
+        let channels = server_engine.channels.lock().unwrap().list.clone();
+        for channel in channels.iter() {
+            server_engine.load_history(channel)?;
+        }
+        server_engine.build_search_index(index_days)?;
+        Ok(server_engine)
+    }
+    /// Reloads the configuration file, settings read on use take effect immediately.
+    pub fn reload_config(&self) -> Result<PathBuf> {
+        let path = self.config.lock().unwrap().path.clone();
+        let config = Config::load(&path)?;
+        self.channels.lock().unwrap().apply_config(&config)?;
+        *self.config.lock().unwrap() = config;
+        Ok(path)
+    }
+    /// Moves the client to another channel, dropping the previous one if it became empty.
+    ///
+    /// The users of both channels are told the client left one and joined the other.
+    pub fn move_client(&self, client: &ArcMut<Client>, channel: ArcMut<Channel>) -> Result<()> {
+        let (nick, old_channel) = {
+            let mut client = client.lock().unwrap();
+            client.unsubscribe_from_channel()?;
+            let old_channel = std::mem::replace(&mut client.channel, channel.clone());
+            (client.nick.clone(), old_channel)
+        };
+        client.lock().unwrap().subscribe_to_channel()?;
+        let old_name = old_channel.lock().unwrap().name.clone();
+        self.announce(
+            &old_channel,
+            client,
+            Event::Part {
+                nick: nick.clone(),
+                channel: old_name,
+            },
+        )?;
+        let name = channel.lock().unwrap().name.clone();
+        self.announce(
+            &channel,
+            client,
+            Event::Join {
+                nick,
+                channel: name,
+            },
+        )?;
+        self.channels.lock().unwrap().prune_channel(old_channel)?;
+        Ok(())
+    }
+    /// Sends the event to the users of the channel except `client`.
+    ///
+    /// Join, part and quit events are skipped for users who turned them off with `/set joins off`.
+    pub fn announce(
+        &self,
+        channel: &ArcMut<Channel>,
+        client: &ArcMut<Client>,
+        event: Event,
+    ) -> Result<()> {
+        let membership = matches!(
+            event,
+            Event::Join { .. } | Event::Part { .. } | Event::Quit { .. }
+        );
+        let msg = ChatMessage::new(event);
+        for member in self.channel_members(channel) {
+            if member == *client {
+                continue;
+            }
+            let mut member = member.lock().unwrap();
+            if membership && !member.settings.joins {
+                continue;
+            }
+            member.rx.push_back(msg.clone())?;
+        }
+        Ok(())
+    }
+    /// Renames the client and tells the users of its channel.
+    pub fn change_nick(&self, client: &ArcMut<Client>, nick: String) -> Result<()> {
+        let (old, channel) = {
+            let mut client = client.lock().unwrap();
+            let old = std::mem::replace(&mut client.nick, nick.clone());
+            client.nick_changed_at = Instant::now();
+            client.update_highlights();
+            (old, client.channel.clone())
+        };
+        self.announce(&channel, client, Event::Nick { old, new: nick })
+    }
+    /// Disconnects the client using the nick, the reason is shown on its terminal.
+    pub fn kick(&self, nick: &str, reason: &str) -> Result<()> {
+        let client = self
+            .clients
+            .lock()
+            .unwrap()
+            .get_client_by_nick(nick)
+            .with_context(|| format!("{} is not connected", nick))?;
+        client.lock().unwrap().disconnect = Some(reason.to_string());
+        Ok(())
+    }
+    /// Bans the nick from the channel, moving it to the default channel if it is there.
+    pub fn ban(&self, channel_name: &str, nick: &str) -> Result<()> {
+        let channel = self
+            .channels
+            .lock()
+            .unwrap()
+            .get_channel(channel_name)
+            .with_context(|| format!("channel {} not found", channel_name))?;
+        channel.lock().unwrap().bans.insert(nick.to_lowercase());
+        let client = self.clients.lock().unwrap().get_client_by_nick(nick);
+        let Some(client) = client.filter(|client| client.lock().unwrap().channel == channel) else {
+            return Ok(());
+        };
+        let default_channel = self.channels.lock().unwrap().get_default_channel()?;
+        self.move_client(&client, default_channel)?;
+        client
+            .lock()
+            .unwrap()
+            .rx
+            .push_back(ChatMessage::server(format!(
+                "You have been banned from {}",
+                highlighted(channel_name)
+            )))?;
+        Ok(())
+    }
+    pub fn unban(&self, channel_name: &str, nick: &str) -> Result<()> {
+        let channel = self
+            .channels
+            .lock()
+            .unwrap()
+            .get_channel(channel_name)
+            .with_context(|| format!("channel {} not found", channel_name))?;
+        if !channel.lock().unwrap().bans.remove(&nick.to_lowercase()) {
+            bail!("{} is not banned from {}", nick, channel_name);
+        }
+        Ok(())
+    }
+    /// Makes the registered account an operator of the channel.
+    pub fn op(&self, channel_name: &str, account: &str) -> Result<()> {
+        let channel = self
+            .channels
+            .lock()
+            .unwrap()
+            .get_channel(channel_name)
+            .with_context(|| format!("channel {} not found", channel_name))?;
+        if !self.users.lock().unwrap().is_registered(account) {
+            bail!("{} is not a registered account", account);
+        }
+        let mut channel = channel.lock().unwrap();
+        if !channel.ops.insert(account.to_lowercase()) {
+            bail!("{} is already an operator of {}", account, channel_name);
+        }
+        channel.send(ChatMessage::server(format!(
+            "{} is now an operator of {}",
+            highlighted(account),
+            highlighted(channel_name)
+        )))
+    }
+    pub fn deop(&self, channel_name: &str, account: &str) -> Result<()> {
+        let channel = self
+            .channels
+            .lock()
+            .unwrap()
+            .get_channel(channel_name)
+            .with_context(|| format!("channel {} not found", channel_name))?;
+        let mut channel = channel.lock().unwrap();
+        if !channel.ops.remove(&account.to_lowercase()) {
+            bail!("{} is not an operator of {}", account, channel_name);
+        }
+        channel.send(ChatMessage::server(format!(
+            "{} is no longer an operator of {}",
+            highlighted(account),
+            highlighted(channel_name)
+        )))
+    }
+    /// Bans the address range server wide and disconnects the clients connected from it.
+    pub fn ip_ban(&self, cidr: &str, reason: &str) -> Result<Vec<String>> {
+        let cidr = self.ip_bans.lock().unwrap().ban(cidr, reason)?;
+        let clients = self.clients.lock().unwrap().list.clone();
+        let mut kicked = Vec::new();
+        for client in clients {
+            let mut client = client.lock().unwrap();
+            if cidr.contains(client.peer.ip()) {
+                client.disconnect = Some(format!("You are banned from this server: {}", reason));
+                kicked.push(client.nick.clone());
+            }
+        }
+        Ok(kicked)
+    }
+    pub fn ip_unban(&self, cidr: &str) -> Result<()> {
+        self.ip_bans.lock().unwrap().unban(cidr)
+    }
+    /// Clients currently in the channel.
+    pub fn channel_members(&self, channel: &ArcMut<Channel>) -> Vec<ArcMut<Client>> {
+        let clients = self.clients.lock().unwrap().list.clone();
+        clients
+            .into_iter()
+            .filter(|client| client.lock().unwrap().channel == *channel)
+            .collect()
+    }
+    /// Sets the channel topic and announces it to the subscribers.
+    pub fn set_topic(&self, channel: &ArcMut<Channel>, nick: &str, topic: String) -> Result<()> {
+        let mut channel = channel.lock().unwrap();
+        channel.topic = Some(topic.clone());
+        channel.send(ChatMessage::new(Event::Topic {
+            nick: nick.to_string(),
+            topic,
+        }))
+    }
+    /// Sets the channel slow mode, `None` disables it, and announces it to the subscribers.
+    pub fn set_slow_mode(
+        &self,
+        channel: &ArcMut<Channel>,
+        nick: &str,
+        slow_mode: Option<u64>,
+    ) -> Result<()> {
+        let mut channel = channel.lock().unwrap();
+        channel.slow_mode = slow_mode;
+        let notice = match slow_mode {
+            Some(seconds) => format!(
+                "{} enabled slow mode, one message every {} seconds",
+                nick, seconds
+            ),
+            None => format!("{} disabled slow mode", nick),
+        };
+        channel.send(ChatMessage::server(notice))
+    }
+    /// Tells every client session to restore its terminal and disconnect.
+    pub fn begin_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+    /// Sends the message to every connected client.
+    pub fn broadcast(&self, msg: ChatMessage) -> Result<()> {
+        let clients = self.clients.lock().unwrap().list.clone();
+        for client in clients {
+            client.lock().unwrap().rx.push_back(msg.clone())?;
+        }
+        Ok(())
+    }
+    /// Writes everything that would be lost on exit to disk.
+    pub fn flush(&self) -> Result<()> {
+        if let Some(storage) = &self.storage {
+            storage.lock().unwrap().flush()?;
+        }
+        self.save_snapshot()
+    }
+    pub fn snapshot(&self) -> Snapshot {
+        let channels = self
+            .channels
+            .lock()
+            .unwrap()
+            .list
+            .iter()
+            .map(|channel| channel.lock().unwrap().to_snapshot())
+            .collect();
+        let accounts = self.users.lock().unwrap().accounts();
+        Snapshot::new(channels, accounts)
+    }
+    pub fn save_snapshot(&self) -> Result<()> {
+        let snapshot = self.config.lock().unwrap().snapshot.clone();
+        if !snapshot.enabled {
+            return Ok(());
+        }
+        self.snapshot().save(snapshot.path)
+    }
+    /// Restores the state of the configured channels and adds the snapshot accounts missing
+    /// from the user store.
+    ///
+    /// Other channels are left out, they would be removed anyway as nobody is in them yet.
+    pub fn restore(&self, snapshot: Snapshot) -> Result<()> {
+        let channels = self.channels.lock().unwrap();
+        for channel_snapshot in snapshot.channels {
+            let Some(channel) = channels.get_channel(&channel_snapshot.name) else {
+                log::debug!(
+                    "channel {} not restored, it is not persistent",
+                    channel_snapshot.name
+                );
+                continue;
+            };
+            channel.lock().unwrap().apply_snapshot(channel_snapshot);
+        }
+        drop(channels);
+        self.users.lock().unwrap().restore(snapshot.accounts)?;
+        Ok(())
+    }
+    /// Indexes the channel logs of the last `days` days.
+    fn build_search_index(&self, days: u64) -> Result<()> {
+        let (Some(storage), Some(search)) = (&self.storage, &self.search) else {
+            return Ok(());
+        };
+        let since = Local::now().date_naive() - chrono::Days::new(days);
+        let mut records = Vec::new();
+        {
+            let storage = storage.lock().unwrap();
+            for target in storage.targets()? {
+                if target.starts_with('@') {
+                    continue;
+                }
+                records.extend(storage.load_since(&target, since)?);
+            }
+        }
+        records.sort_by_key(|record| record.timestamp);
+        let mut search = search.lock().unwrap();
+        for record in records {
+            search.add(record);
+        }
+        Ok(())
+    }
+    /// Returns the channel with the given name, creating it and loading its logged history if needed.
+    pub fn open_channel(&self, name: &str) -> Result<ArcMut<Channel>> {
+        let channel = {
+            let mut channels = self.channels.lock().unwrap();
+            if let Some(channel) = channels.get_channel(name) {
+                return Ok(channel);
+            }
+            channels.create_channel(name.to_string(), false)
+        };
+        self.load_history(&channel)?;
+        Ok(channel)
+    }
+    pub fn load_history(&self, channel: &ArcMut<Channel>) -> Result<()> {
+        let Some(storage) = &self.storage else {
+            return Ok(());
+        };
+        let (name, history_lines) = {
+            let channel = channel.lock().unwrap();
+            (channel.name.clone(), channel.history_lines)
+        };
+        let records = storage.lock().unwrap().load_recent(&name, history_lines)?;
+        let mut channel = channel.lock().unwrap();
+        for record in records {
+            channel.add_history(record.to_message());
+        }
+        Ok(())
+    }
+    /// Delivers the message to every subscriber of the channel and appends it to the chat log.
+    pub fn publish(&self, channel: &ArcMut<Channel>, msg: ChatMessage) -> Result<()> {
+        let name = channel.lock().unwrap().name.clone();
+        self.metrics.count_message(&name);
+        if let Some(record) = LogRecord::from_message(&name, &msg) {
+            if let Some(search) = &self.search {
+                search.lock().unwrap().add(record.clone());
+            }
+            self.log(record);
+        }
+        let mut ignoring = Vec::new();
+        let clients = self.clients.lock().unwrap().list.clone();
+        for client in clients {
+            let mut client = client.lock().unwrap();
+            if client.channel != *channel {
+                continue;
+            }
+            if client.is_ignoring(&msg) {
+                ignoring.push(client.rx.clone());
+                continue;
+            }
+            if let Some(away) = client.away.as_mut() {
+                *away.channel_messages.entry(name.clone()).or_default() += 1;
+            }
+        }
+        channel.lock().unwrap().send_except(msg, &ignoring)
+    }
+    /// Delivers a private message to both ends of the conversation and appends it to the chat log.
+    pub fn send_private(
+        &self,
+        from: &ArcMut<Client>,
+        to: &ArcMut<Client>,
+        kind: MessageKind,
+        text: String,
+    ) -> Result<()> {
+        let from_nick = from.lock().unwrap().nick.clone();
+        let to_nick = to.lock().unwrap().nick.clone();
+        let msg = ChatMessage::new(Event::Message {
+            kind,
+            nick: from_nick.clone(),
+            to: Some(to_nick.clone()),
+            text,
+        });
+        if let Some(record) = LogRecord::from_message(&private_target(&from_nick, &to_nick), &msg) {
+            self.log(record);
+        }
+        {
+            let mut to = to.lock().unwrap();
+            if to.is_ignoring(&msg) {
+                // the sender is not told, like a message the target never read
+                return Ok(());
+            }
+            if let Some(away) = to.away.as_mut() {
+                *away.private_messages.entry(from_nick.clone()).or_default() += 1;
+            }
+            to.rx.push_back(msg.clone())?;
+        }
+        if from != to {
+            from.lock().unwrap().rx.push_back(msg)?;
+        }
+        Ok(())
+    }
+    fn log(&self, record: LogRecord) {
+        let Some(storage) = &self.storage else {
+            return;
+        };
+        if let Err(e) = storage.lock().unwrap().append(&record) {
+            log::error!("unable to log message: {}", e);
         }
     }
     pub fn as_arc_mut(&self) -> ArcMut<Self> {
         ArcMut::new(self.clone())
     }
 }
-impl Default for ServerEngine {
-    fn default() -> Self {
-        Self::new()
-    }
-}
 
 #[derive(Debug, Clone)]
 pub struct ClientList {
     pub list: Vec<ArcMut<Client>>,
+    next_id: usize,
 }
 impl ClientList {
+    pub fn new() -> Self {
+        ClientList {
+            list: Vec::new(),
+            next_id: 0,
+        }
+    }
+    pub fn next_id(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+    pub fn add_client(&mut self, client: ArcMut<Client>) -> Result<()> {
+        self.list.push(client);
+        Ok(())
+    }
+    pub fn remove_client(&mut self, id: usize) -> Result<()> {
+        let index = self
+            .list
+            .iter()
+            .position(|x| x.lock().unwrap().id == id)
+            .with_context(|| format!("client {} not found", id))?;
+        self.list.remove(index);
+        Ok(())
+    }
     pub fn len(&self) -> usize {
         self.list.len()
     }
-    pub fn get_client(&self, id: usize) -> ArcMut<Client> {
-        self.list[id].clone()
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+    pub fn get_client(&self, id: usize) -> Option<ArcMut<Client>> {
+        self.list
+            .iter()
+            .find(|x| x.lock().unwrap().id == id)
+            .cloned()
+    }
+    pub fn get_client_by_nick(&self, nick: &str) -> Option<ArcMut<Client>> {
+        self.list
+            .iter()
+            .find(|x| x.lock().unwrap().nick.eq_ignore_ascii_case(nick))
+            .cloned()
+    }
+}
+impl Default for ClientList {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct ChannelList {
     pub list: Vec<ArcMut<Channel>>,
+    pub default: String,
+    pub history_lines: usize,
+    next_id: usize,
 }
 impl ChannelList {
-    pub fn new() -> Self {
-        ChannelList { list: Vec::new() }
+    pub fn new(default: String, history_lines: usize) -> Self {
+        let mut channels = ChannelList {
+            list: Vec::new(),
+            default: default.clone(),
+            history_lines,
+            next_id: 0,
+        };
+        channels.create_channel(default, true);
+        channels
+    }
+    pub fn from_config(config: &Config) -> Self {
+        let mut channels = ChannelList::new(
+            config.channels.default.clone(),
+            config.storage.history_lines,
+        );
+        for name in config.channels.persistent.iter() {
+            match channels.get_channel(name) {
+                Some(channel) => channel.lock().unwrap().persistent = true,
+                None => {
+                    channels.create_channel(name.clone(), true);
+                }
+            }
+        }
+        channels
+    }
+    /// Makes the default and configured channels persistent and releases the ones no longer listed.
+    pub fn apply_config(&mut self, config: &Config) -> Result<()> {
+        self.default = config.channels.default.clone();
+        self.history_lines = config.storage.history_lines;
+        let mut persistent = config.channels.persistent.clone();
+        persistent.push(self.default.clone());
+        for name in persistent.iter() {
+            if self.get_channel(name).is_none() {
+                self.create_channel(name.clone(), true);
+            }
+        }
+        for channel in self.list.clone() {
+            {
+                let mut channel = channel.lock().unwrap();
+                channel.persistent = persistent.contains(&channel.name);
+                channel.history_lines = self.history_lines;
+            }
+            self.prune_channel(channel)?;
+        }
+        Ok(())
+    }
+    pub fn create_channel(&mut self, name: String, persistent: bool) -> ArcMut<Channel> {
+        let mut channel = Channel::new(self.next_id, name);
+        channel.persistent = persistent;
+        channel.history_lines = self.history_lines;
+        self.next_id += 1;
+        let channel = channel.as_arc_mut();
+        self.list.push(channel.clone());
+        channel
     }
     pub fn add_channel(&mut self, channel: ArcMut<Channel>) -> Result<()> {
         self.list.push(channel);
         Ok(())
     }
-    pub fn remove_channel(&mut self, client: ArcMut<Channel>) -> Result<()> {
-        let index = self.list.iter().position(|x| *x == client).unwrap();
+    pub fn remove_channel(&mut self, channel: ArcMut<Channel>) -> Result<()> {
+        let index = self
+            .list
+            .iter()
+            .position(|x| *x == channel)
+            .context("channel not found")?;
         self.list.remove(index);
         Ok(())
     }
+    /// Removes the channel if it is not persistent and nobody is subscribed to it anymore.
+    pub fn prune_channel(&mut self, channel: ArcMut<Channel>) -> Result<bool> {
+        if !channel.lock().unwrap().is_disposable() {
+            return Ok(false);
+        }
+        self.remove_channel(channel)?;
+        Ok(true)
+    }
     pub fn len(&self) -> usize {
         self.list.len()
     }
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
     pub fn as_arc_mut(&self) -> ArcMut<Self> {
         ArcMut::new(self.clone())
     }
-    pub fn get_default_channel(&self) -> ArcMut<Channel> {
-        self.list[0].clone()
+    pub fn get_default_channel(&self) -> Result<ArcMut<Channel>> {
+        self.get_channel(&self.default)
+            .with_context(|| format!("default channel {} not found", self.default))
     }
-    pub fn get_channel(self, name: String) -> Option<ArcMut<Channel>> {
-        let channel = self
-            .list
+    pub fn get_channel(&self, name: &str) -> Option<ArcMut<Channel>> {
+        self.list
             .iter()
+            .find(|x| x.lock().unwrap().name == name)
             .cloned()
-            .find(|x| x.lock().unwrap().name == name);
-
-        channel
     }
 }
 impl Default for ChannelList {
     fn default() -> Self {
-        Self::new()
+        Self::from_config(&Config::default())
     }
 }
 #[derive(Debug, Clone)]
 pub struct Channel {
     pub id: usize,
     pub name: String,
+    pub persistent: bool,
     pub subscribers: Vec<MessageBus<ChatMessage>>,
+    pub history: VecDeque<ChatMessage>,
+    pub history_lines: usize,
+    pub topic: Option<String>,
+    /// Lowercase accounts of the channel operators, only logged in users can be operators.
+    pub ops: BTreeSet<String>,
+    /// Lowercase nicks not allowed to join.
+    pub bans: BTreeSet<String>,
+    /// Seconds a non operator has to wait between two messages, `None` when slow mode is off.
+    pub slow_mode: Option<u64>,
+    /// Lowercase nick to the time of its last message, used by slow mode.
+    last_spoke: HashMap<String, Instant>,
 }
 impl Channel {
     pub fn new(id: usize, name: String) -> Self {
         Channel {
             id,
             name,
+            persistent: false,
             subscribers: Vec::new(),
+            history: VecDeque::new(),
+            history_lines: 0,
+            topic: None,
+            ops: BTreeSet::new(),
+            bans: BTreeSet::new(),
+            slow_mode: None,
+            last_spoke: HashMap::new(),
+        }
+    }
+    /// True when the account a client is logged in to operates the channel.
+    pub fn is_op(&self, account: Option<&str>) -> bool {
+        account.is_some_and(|account| self.ops.contains(&account.to_lowercase()))
+    }
+    pub fn is_banned(&self, nick: &str) -> bool {
+        self.bans.contains(&nick.to_lowercase())
+    }
+    pub fn to_snapshot(&self) -> ChannelSnapshot {
+        ChannelSnapshot {
+            name: self.name.clone(),
+            topic: self.topic.clone(),
+            ops: self.ops.clone(),
+            bans: self.bans.clone(),
+            slow_mode: self.slow_mode,
+        }
+    }
+    pub fn apply_snapshot(&mut self, snapshot: ChannelSnapshot) {
+        self.topic = snapshot.topic;
+        self.ops = snapshot.ops;
+        self.bans = snapshot.bans;
+        self.slow_mode = snapshot.slow_mode;
+    }
+    /// Seconds the nick still has to wait before speaking under slow mode, operators are exempt.
+    pub fn slow_mode_wait(&mut self, nick: &str, account: Option<&str>) -> Option<u64> {
+        let interval = Duration::from_secs(self.slow_mode?);
+        if self.is_op(account) {
+            return None;
+        }
+        let nick = nick.to_lowercase();
+        if let Some(elapsed) = self.last_spoke.get(&nick).map(|last| last.elapsed()) {
+            if elapsed < interval {
+                return Some((interval - elapsed).as_secs_f64().ceil() as u64);
+            }
+        }
+        self.last_spoke.retain(|_, last| last.elapsed() < interval);
+        self.last_spoke.insert(nick, Instant::now());
+        None
+    }
+    pub fn send(&mut self, msg: ChatMessage) -> Result<()> {
+        self.send_except(msg, &[])
+    }
+    /// Sends the message to the subscribers not in `skipped`, it is kept in the history anyway.
+    pub fn send_except(
+        &mut self,
+        msg: ChatMessage,
+        skipped: &[MessageBus<ChatMessage>],
+    ) -> Result<()> {
+        for subscriber in self.subscribers.iter_mut() {
+            if skipped.contains(subscriber) {
+                continue;
+            }
+            subscriber.push_back(msg.clone())?;
+        }
+        self.add_history(msg);
+        Ok(())
+    }
+    pub fn add_history(&mut self, msg: ChatMessage) {
+        self.history.push_back(msg);
+        while self.history.len() > self.history_lines {
+            self.history.pop_front();
         }
     }
     pub fn add_subscriber(&mut self, subscriber: MessageBus<ChatMessage>) -> Result<()> {
@@ -114,10 +766,13 @@ impl Channel {
             .subscribers
             .iter()
             .position(|x| *x == subscriber)
-            .unwrap();
+            .context("subscriber not found")?;
         self.subscribers.remove(index);
         Ok(())
     }
+    pub fn is_disposable(&self) -> bool {
+        !self.persistent && self.subscribers.is_empty()
+    }
     pub fn as_arc_mut(&self) -> ArcMut<Self> {
         ArcMut::new(self.clone())
     }
@@ -129,31 +784,64 @@ impl Channel {
 pub struct ClientStream {
     pub stream: TcpStream,
     pub client: ArcMut<Client>,
-    pub service_bus: ArcMut<MessageBus<CmdMessage>>,
+    pub server: ServerEngine,
 }
 impl ClientStream {
-    pub fn new(
-        stream: TcpStream,
-        client: ArcMut<Client>,
-        service_bus: ArcMut<MessageBus<CmdMessage>>,
-    ) -> Self {
+    pub fn new(stream: TcpStream, client: ArcMut<Client>, server: ServerEngine) -> Self {
         ClientStream {
             stream,
             client,
-            service_bus,
+            server,
         }
     }
     pub fn shutdown(&mut self) -> Result<()> {
         self.stream.shutdown(std::net::Shutdown::Both)?;
         Ok(())
     }
+    /// Writes to the client connection, counting the bytes sent.
+    pub fn write(&mut self, data: &str) -> Result<()> {
+        self.write_bytes(data.as_bytes())
+    }
+    pub fn write_bytes(&mut self, data: &[u8]) -> Result<()> {
+        let n = self.stream.write(data)?;
+        self.server.metrics.add_bytes_sent(n);
+        Ok(())
+    }
+    /// Redraws the chat area of the client terminal, timing the render.
+    pub fn render(&mut self) -> Result<()> {
+        let started_at = Instant::now();
+        let terminal = formatted_terminal(&mut self.client.lock().unwrap().terminal);
+        self.server.metrics.observe_render(started_at.elapsed());
+        self.write(&terminal)
+    }
+    /// Detaches the client from its channel and from the server client list.
+    ///
+    /// The users left in the channel are told it quit, unless the server is shutting down.
+    pub fn cleanup(&mut self, reason: Option<String>) -> Result<()> {
+        let (id, nick, channel) = {
+            let mut client = self.client.lock().unwrap();
+            client.unsubscribe_from_channel()?;
+            (client.id, client.nick.clone(), client.channel.clone())
+        };
+        self.server.clients.lock().unwrap().remove_client(id)?;
+        if !self.server.is_shutting_down() {
+            self.server
+                .announce(&channel, &self.client, Event::Quit { nick, reason })?;
+        }
+        self.server
+            .channels
+            .lock()
+            .unwrap()
+            .prune_channel(channel)?;
+        Ok(())
+    }
 }
 impl Clone for ClientStream {
     fn clone(&self) -> Self {
         ClientStream {
             stream: self.stream.try_clone().unwrap(),
             client: self.client.clone(),
-            service_bus: self.service_bus.clone(),
+            server: self.server.clone(),
         }
     }
 }
@@ -198,7 +886,7 @@ impl<T: Clone> PartialEq for MessageBus<T> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct ArcMut<T>(pub Arc<Mutex<T>>);
 
 impl<T> ArcMut<T> {
@@ -206,6 +894,11 @@ impl<T> ArcMut<T> {
         ArcMut(Arc::new(Mutex::new(data)))
     }
 }
+impl<T> Clone for ArcMut<T> {
+    fn clone(&self) -> Self {
+        ArcMut(self.0.clone())
+    }
+}
 impl<T> Deref for ArcMut<T> {
     type Target = Arc<Mutex<T>>;
     fn deref(&self) -> &Self::Target {
@@ -221,16 +914,44 @@ impl<T> PartialEq for ArcMut<T> {
 #[derive(Debug, Clone)]
 pub struct Client {
     pub id: usize,
+    pub peer: SocketAddr,
     pub nick: String,
+    /// Account the client is logged in to, if any.
+    pub account: Option<String>,
+    pub nick_changed_at: Instant,
+    /// Set to make the session disconnect, showing the reason.
+    pub disconnect: Option<String>,
+    /// Last time a line was received from the client.
+    pub last_activity: Instant,
+    /// Away status, `None` while the client is present.
+    pub away: Option<Away>,
+    /// Preferences changed with `/set`.
+    pub settings: Settings,
+    /// Masks of the nicks whose messages are not delivered to the client.
+    pub ignores: Vec<String>,
     pub rx: MessageBus<ChatMessage>,
     pub terminal: Terminal,
     pub channel: ArcMut<Channel>,
 }
 impl Client {
-    pub fn new(id: usize, nick: String, terminal_rows: usize, channel: ArcMut<Channel>) -> Self {
+    pub fn new(
+        id: usize,
+        peer: SocketAddr,
+        nick: String,
+        terminal_rows: usize,
+        channel: ArcMut<Channel>,
+    ) -> Self {
         let mut inner_self = Client {
             id,
+            peer,
             nick,
+            account: None,
+            nick_changed_at: Instant::now(),
+            disconnect: None,
+            last_activity: Instant::now(),
+            away: None,
+            settings: Settings::default(),
+            ignores: Vec::new(),
             rx: MessageBus::new(),
             terminal: Terminal::new(terminal_rows),
             channel: channel.clone(),
@@ -238,17 +959,56 @@ impl Client {
         inner_self
             .terminal
             .set_prompt(channel.lock().unwrap().name.clone());
+        inner_self.update_highlights();
         inner_self
     }
+    /// True when the message was sent by a nick matching one of the ignore masks.
+    pub fn is_ignoring(&self, msg: &ChatMessage) -> bool {
+        let Event::Message { nick, .. } = &msg.event else {
+            return false;
+        };
+        self.ignores.iter().any(|mask| mask_matches(mask, nick))
+    }
+    /// Makes the chat highlight the current nick and highlight words.
+    pub fn update_highlights(&mut self) {
+        self.terminal
+            .chat
+            .set_highlights(self.nick.clone(), self.settings.highlights.clone());
+    }
     pub fn subscribe_to_channel(&mut self) -> Result<()> {
-        self.channel
-            .lock()
-            .unwrap()
-            .add_subscriber(self.rx.clone())?;
+        let history = {
+            let mut channel = self.channel.lock().unwrap();
+            channel.add_subscriber(self.rx.clone())?;
+            channel.history.clone()
+        };
+        for mut msg in history {
+            if !self.is_ignoring(&msg) {
+                msg.replayed = true;
+                self.rx.push_back(msg)?;
+            }
+        }
         self.terminal
             .set_prompt(self.channel.lock().unwrap().name.clone());
         Ok(())
     }
+    /// Connection id, peer address and nick attached to every session log line.
+    pub fn log_context(&self) -> (usize, SocketAddr, String) {
+        (self.id, self.peer, self.nick.clone())
+    }
+    /// Records input from the client, returns the automatic away status it cleared if any.
+    pub fn touch(&mut self) -> Option<Away> {
+        self.last_activity = Instant::now();
+        if !self.away.as_ref().is_some_and(|away| away.automatic) {
+            return None;
+        }
+        self.away.take()
+    }
+    /// True when the client is logged in to the account owning its current nick.
+    pub fn is_identified(&self) -> bool {
+        self.account
+            .as_ref()
+            .is_some_and(|account| account.eq_ignore_ascii_case(&self.nick))
+    }
     pub fn unsubscribe_from_channel(&mut self) -> Result<()> {
         self.channel
             .lock()
@@ -261,6 +1021,103 @@ impl Client {
     }
 }
 
+/// Matches a nick against a mask ignoring case, `*` stands for any text and `?` for one character.
+pub fn mask_matches(mask: &str, nick: &str) -> bool {
+    let mask = mask.to_lowercase().chars().collect::<Vec<char>>();
+    let nick = nick.to_lowercase().chars().collect::<Vec<char>>();
+    // position after the last `*` in the mask and the nick position it was tried at
+    let (mut m, mut n, mut star) = (0, 0, None);
+    while n < nick.len() {
+        match mask.get(m) {
+            Some('*') => {
+                star = Some((m + 1, n));
+                m += 1;
+            }
+            Some(&c) if c == '?' || c == nick[n] => {
+                m += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_m, star_n)) => {
+                    star = Some((star_m, star_n + 1));
+                    m = star_m;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    mask[m..].iter().all(|c| *c == '*')
+}
+
+/// Per client preferences, kept for the session.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Shows other users joining, leaving or quitting the channel.
+    pub joins: bool,
+    /// Words highlighting a message like the nick does.
+    pub highlights: Vec<String>,
+}
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            joins: true,
+            highlights: Vec::new(),
+        }
+    }
+}
+
+/// Away status of a client, with what it missed in the meantime.
+#[derive(Debug, Clone)]
+pub struct Away {
+    pub message: String,
+    pub since: Instant,
+    /// Set by the idle check rather than by the client.
+    pub automatic: bool,
+    /// Channel name to messages published there while away.
+    pub channel_messages: BTreeMap<String, usize>,
+    /// Nick to private messages received from it while away.
+    pub private_messages: BTreeMap<String, usize>,
+}
+impl Away {
+    pub fn new(message: String, automatic: bool) -> Self {
+        Away {
+            message,
+            since: Instant::now(),
+            automatic,
+            channel_messages: BTreeMap::new(),
+            private_messages: BTreeMap::new(),
+        }
+    }
+    /// Lines summing up the missed messages, shown when the client comes back.
+    pub fn digest(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if !self.channel_messages.is_empty() {
+            let channels = self
+                .channel_messages
+                .iter()
+                .map(|(channel, count)| format!("{} messages in {}", count, highlighted(channel)))
+                .collect::<Vec<String>>();
+            lines.push(format!("While you were away: {}", channels.join(", ")));
+        }
+        if !self.private_messages.is_empty() {
+            let nicks = self
+                .private_messages
+                .iter()
+                .map(|(nick, count)| format!("{} ({})", highlighted(nick), count))
+                .collect::<Vec<String>>();
+            lines.push(format!(
+                "While you were away: private messages from {}",
+                nicks.join(", ")
+            ));
+        }
+        if lines.is_empty() {
+            lines.push("While you were away: no new messages".to_string());
+        }
+        lines
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Terminal {
     pub start_cmd: String,
@@ -291,8 +1148,9 @@ impl Terminal {
     pub fn get_terminal_lines(&self) -> usize {
         self.chat.number_of_lines()
     }
-    pub fn add_message(&mut self, msg: ChatMessage) {
-        self.chat.add_text(msg);
+    /// Adds the message to the chat, returns whether it mentions the client.
+    pub fn add_message(&mut self, msg: ChatMessage) -> bool {
+        self.chat.add_text(msg)
     }
     pub fn as_arc_mut(&self) -> ArcMut<Self> {
         ArcMut::new(self.clone())
@@ -302,42 +1160,108 @@ impl Terminal {
     }
 }
 
+/// Prefix of the nicks given to new clients, reserved so they can never be registered.
+pub const ANONYMOUS_PREFIX: &str = "Anonymous-";
+
+/// Nick a client gets on connection or when it loses a registered nick.
+pub fn anonymous_nick(id: usize) -> String {
+    format!("{}{}", ANONYMOUS_PREFIX, id)
+}
+
+/// True for the nicks the server hands out, whatever their case.
+pub fn is_anonymous_nick(nick: &str) -> bool {
+    nick.get(..ANONYMOUS_PREFIX.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(ANONYMOUS_PREFIX))
+}
+
+/// Mentions kept for `/mentions`.
+const MAX_MENTIONS: usize = 20;
+
+/// Characters of text per chat line, longer messages are wrapped.
+const WRAP_WIDTH: usize = 60;
+
 #[derive(Debug, Clone)]
 pub struct Chat {
     chat: VecDeque<String>,
     number_of_lines: usize,
+    /// Nick of the client reading the chat, messages containing it are highlighted.
+    nick: String,
+    /// Other words highlighting a message.
+    highlights: Vec<String>,
+    /// Most recent messages mentioning the client, oldest first.
+    pub mentions: VecDeque<ChatMessage>,
 }
 impl Chat {
     pub fn new(number_of_lines: usize) -> Self {
         Chat {
             chat: VecDeque::new(),
             number_of_lines,
+            nick: String::new(),
+            highlights: Vec::new(),
+            mentions: VecDeque::new(),
         }
     }
-    pub fn add_text(&mut self, msg: ChatMessage) {
-        let msg_text = msg.payload;
-        let nick = format!("[{}]: ", msg.nick);
-        let padding = nick.len();
-        let payload = msg_text
-            .chars()
-            .collect::<Vec<char>>()
-            .chunks(60)
+    pub fn set_highlights(&mut self, nick: String, highlights: Vec<String>) {
+        self.nick = nick;
+        self.highlights = highlights;
+    }
+    /// True for new messages of other users containing the nick or a highlight word.
+    fn is_mention(&self, msg: &ChatMessage) -> bool {
+        let Event::Message { nick, text, .. } = &msg.event else {
+            return false;
+        };
+        !msg.replayed
+            && !nick.eq_ignore_ascii_case(&self.nick)
+            && std::iter::once(&self.nick)
+                .chain(self.highlights.iter())
+                .any(|word| contains_word(text, word))
+    }
+    /// Adds the message to the chat, returns whether it mentions the client.
+    pub fn add_text(&mut self, msg: ChatMessage) -> bool {
+        let mentioned = self.is_mention(&msg);
+        let prefix = match mentioned {
+            true => msg
+                .prefix(RenderStyle::Plain)
+                .black()
+                .on_yellow()
+                .to_string(),
+            false => msg.prefix(RenderStyle::Ansi),
+        };
+        let padding = strip_ansi(&prefix).chars().count();
+        // the plain text is wrapped first so no escape sequence is ever cut in two
+        let (text, highlights) = msg.text();
+        let mut breaks = text
+            .char_indices()
+            .map(|(index, _)| index)
+            .step_by(WRAP_WIDTH)
+            .collect::<Vec<usize>>();
+        if breaks.is_empty() {
+            breaks.push(0);
+        }
+        breaks.push(text.len());
+        let payload = breaks
+            .windows(2)
             .enumerate()
-            .map(|(i, chunks)| {
-                let padded_chunk = if i == 0 {
-                    nick.clone() + &chunks.iter().collect::<String>()
-                } else {
-                    " ".repeat(padding) + &chunks.iter().collect::<String>()
-                };
-                padded_chunk + "\r\n"
+            .map(|(i, range)| {
+                let line = msg.paint(&text, &highlights, range[0]..range[1], mentioned);
+                match i {
+                    0 => format!("{}{}\r\n", prefix, line),
+                    _ => format!("{}{}\r\n", " ".repeat(padding), line),
+                }
             })
             .collect::<Vec<String>>();
-        if self.chat.len() >= self.number_of_lines {
-            self.chat.drain(..payload.len());
-            self.chat.extend(payload);
-            return;
+        if mentioned {
+            if self.mentions.len() >= MAX_MENTIONS {
+                self.mentions.pop_front();
+            }
+            self.mentions.push_back(msg);
         }
         self.chat.extend(payload);
+        // a message longer than the chat area only keeps its last lines
+        while self.chat.len() > self.number_of_lines {
+            self.chat.pop_front();
+        }
+        mentioned
     }
 
     pub fn get_chat(&self) -> String {
@@ -355,29 +1279,401 @@ impl Chat {
     }
 }
 
+/// Whether `word` appears in `text` ignoring case, not as part of a longer nick or word.
+fn contains_word(text: &str, word: &str) -> bool {
+    let is_word_char = |c: char| c.is_alphanumeric() || "-_[]{}|^`".contains(c);
+    let (text, word) = (text.to_lowercase(), word.to_lowercase());
+    if word.is_empty() {
+        return false;
+    }
+    text.match_indices(&word).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + word.len()..].chars().next();
+        !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+    })
+}
+
 impl PartialEq for Chat {
     fn eq(&self, other: &Self) -> bool {
         self.chat == other.chat
     }
 }
-#[derive(Debug, Clone)]
+/// How a message is meant to be read, and rendered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageKind {
+    /// Conversation, `[nick]: text`.
+    #[default]
+    Chat,
+    /// `/me`, `* nick text`.
+    Action,
+    /// Information nobody should answer automatically, `-nick- text`.
+    Notice,
+}
+
+/// Something that happened, with the fields needed to present it any way.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Event {
+    /// A chat line, action or notice, `to` is set for private messages.
+    Message {
+        kind: MessageKind,
+        nick: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to: Option<String>,
+        text: String,
+    },
+    Join {
+        nick: String,
+        channel: String,
+    },
+    Part {
+        nick: String,
+        channel: String,
+    },
+    Quit {
+        nick: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    Nick {
+        old: String,
+        new: String,
+    },
+    Topic {
+        nick: String,
+        topic: String,
+    },
+    /// Server information and command replies.
+    System {
+        text: String,
+        /// Byte ranges of `text` to show highlighted, like nicks and commands.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        highlights: Vec<(usize, usize)>,
+    },
+    Error {
+        text: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        highlights: Vec<(usize, usize)>,
+    },
+}
+
+/// Marks a part of a server reply to be highlighted when rendered, see `ChatMessage::server`.
+pub fn highlighted(value: impl fmt::Display) -> String {
+    format!("{}{}{}", HIGHLIGHT_MARK, value, HIGHLIGHT_MARK)
+}
+
+/// Characters of a reply as shown to the client, without the highlight marks.
+pub fn visible_length(text: &str) -> usize {
+    text.chars().filter(|c| *c != HIGHLIGHT_MARK).count()
+}
+
+/// The text of a reply without its highlight marks, for output that is never coloured.
+pub fn unhighlighted(text: &str) -> String {
+    parse_highlights(text).0
+}
+
+/// Delimits the highlighted parts of a reply until it becomes a `ChatMessage`.
+const HIGHLIGHT_MARK: char = '\u{2}';
+
+/// Removes the highlight marks, returning the plain text and the byte ranges they delimited.
+fn parse_highlights(marked: &str) -> (String, Vec<(usize, usize)>) {
+    let mut text = String::with_capacity(marked.len());
+    let mut highlights = Vec::new();
+    let mut start = None;
+    for c in marked.chars() {
+        if c != HIGHLIGHT_MARK {
+            text.push(c);
+            continue;
+        }
+        match start.take() {
+            Some(start) => highlights.push((start, text.len())),
+            None => start = Some(text.len()),
+        }
+    }
+    (text, highlights)
+}
+
+/// Presentation of an event on a client.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderStyle {
+    /// Colored, for the chat terminal.
+    Ansi,
+    Plain,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
-    pub nick: String,
-    pub payload: String,
+    pub timestamp: DateTime<Local>,
+    #[serde(flatten)]
+    pub event: Event,
+    /// Set on channel history replayed to a joining client, it never counts as a mention.
+    #[serde(skip)]
+    pub replayed: bool,
 }
 impl ChatMessage {
-    pub fn new(nick: String, msg: String) -> Self {
-        ChatMessage { nick, payload: msg }
+    pub fn new(event: Event) -> Self {
+        ChatMessage {
+            timestamp: Local::now(),
+            event,
+            replayed: false,
+        }
+    }
+    pub fn message(kind: MessageKind, nick: String, text: String) -> Self {
+        ChatMessage::new(Event::Message {
+            kind,
+            nick,
+            to: None,
+            text,
+        })
+    }
+    /// A server reply, the parts wrapped with `highlighted` are kept as highlight ranges.
+    pub fn server(text: String) -> Self {
+        let (text, highlights) = parse_highlights(&text);
+        ChatMessage::new(Event::System { text, highlights })
+    }
+    pub fn error(text: String) -> Self {
+        let (text, highlights) = parse_highlights(&text);
+        ChatMessage::new(Event::Error { text, highlights })
+    }
+    /// Returns the prefix and the text of the event as shown to a client.
+    pub fn render(&self, style: RenderStyle) -> (String, String) {
+        let (text, highlights) = self.text();
+        let text = match style {
+            RenderStyle::Ansi => self.paint(&text, &highlights, 0..text.len(), false),
+            RenderStyle::Plain => text,
+        };
+        (self.prefix(style), text)
+    }
+    fn prefix(&self, style: RenderStyle) -> String {
+        let ansi = style == RenderStyle::Ansi;
+        let marker = |marker: &str, color: Color| match ansi {
+            true => format!("{} ", marker.color(color)),
+            false => format!("{} ", marker),
+        };
+        match &self.event {
+            Event::Message { kind, nick, to, .. } => {
+                let name = match to {
+                    Some(to) => format!("{} -> {}", nick, to),
+                    None => nick.clone(),
+                };
+                match kind {
+                    MessageKind::Chat => format!("[{}]: ", name),
+                    MessageKind::Action => format!("* {} ", name),
+                    MessageKind::Notice => format!("-{}- ", name),
+                }
+            }
+            Event::Join { .. } => marker("-->", Color::Green),
+            Event::Part { .. } | Event::Quit { .. } => marker("<--", Color::Red),
+            Event::Nick { .. } | Event::Topic { .. } => marker("---", Color::Blue),
+            Event::System { .. } | Event::Error { .. } if ansi => server_prefix(),
+            Event::System { .. } | Event::Error { .. } => "[SERVER]: ".to_string(),
+        }
+    }
+    /// The text of the event without colours, with the byte ranges shown highlighted.
+    fn text(&self) -> (String, Vec<(usize, usize)>) {
+        match &self.event {
+            Event::Message { text, .. } => (text.clone(), Vec::new()),
+            Event::Join { nick, channel } => {
+                join_highlighted(&[(nick, true), (" joined ", false), (channel, false)])
+            }
+            Event::Part { nick, channel } => {
+                join_highlighted(&[(nick, true), (" left ", false), (channel, false)])
+            }
+            Event::Quit { nick, reason } => match reason {
+                Some(reason) => {
+                    join_highlighted(&[(nick, true), (" quit: ", false), (reason, false)])
+                }
+                None => join_highlighted(&[(nick, true), (" quit", false)]),
+            },
+            Event::Nick { old, new } => {
+                join_highlighted(&[(old, true), (" is now known as ", false), (new, true)])
+            }
+            Event::Topic { nick, topic } => {
+                join_highlighted(&[(nick, true), (" set the topic to: ", false), (topic, false)])
+            }
+            Event::System { text, highlights } | Event::Error { text, highlights } => {
+                (text.clone(), highlights.clone())
+            }
+        }
+    }
+    /// Colours the `range` of `text`, which can be one wrapped line of it.
+    ///
+    /// Highlights are yellow, errors red, and everything is bold with `emphasis`.
+    fn paint(
+        &self,
+        text: &str,
+        highlights: &[(usize, usize)],
+        range: Range<usize>,
+        emphasis: bool,
+    ) -> String {
+        let is_error = matches!(self.event, Event::Error { .. });
+        let style = |part: &str, highlighted: bool| {
+            if part.is_empty() {
+                return String::new();
+            }
+            let mut styled = part.normal();
+            if highlighted {
+                styled = styled.yellow();
+            } else if is_error {
+                styled = styled.red();
+            }
+            if emphasis {
+                styled = styled.bold();
+            }
+            styled.to_string()
+        };
+        let mut painted = String::new();
+        let mut position = range.start;
+        for &(start, end) in highlights {
+            let (start, end) = (start.clamp(position, range.end), end.min(range.end));
+            if start >= end {
+                continue;
+            }
+            let (Some(before), Some(part)) = (text.get(position..start), text.get(start..end))
+            else {
+                continue;
+            };
+            painted += &style(before, false);
+            painted += &style(part, true);
+            position = end;
+        }
+        painted += &style(text.get(position..range.end).unwrap_or_default(), false);
+        painted
     }
 }
 
+/// Concatenates the parts, returning the byte ranges of those flagged as highlighted.
+fn join_highlighted(parts: &[(&str, bool)]) -> (String, Vec<(usize, usize)>) {
+    let mut text = String::new();
+    let mut highlights = Vec::new();
+    for (part, highlighted) in parts {
+        if *highlighted {
+            highlights.push((text.len(), text.len() + part.len()));
+        }
+        text += part;
+    }
+    (text, highlights)
+}
+
+fn server_prefix() -> String {
+    format!("[{}]: ", "SERVER".blue().bold())
+}
+
+#[derive(Debug, Clone)]
+pub enum CmdSource {
+    /// A client command, its replies are sent back to the session through `reply`.
+    Client {
+        id: usize,
+        reply: Sender<Vec<ChatMessage>>,
+    },
+    Console,
+}
+
 #[derive(Debug, Clone)]
 pub struct CmdMessage {
-    pub id: usize,
+    pub source: CmdSource,
     pub payload: String,
 }
 impl CmdMessage {
-    pub fn new(id: usize, msg: String) -> Self {
-        CmdMessage { id, payload: msg }
+    /// Returns the message with the receiving end of its reply channel.
+    pub fn new(id: usize, msg: String) -> (Self, Receiver<Vec<ChatMessage>>) {
+        let (reply, replies) = mpsc::channel();
+        let message = CmdMessage {
+            source: CmdSource::Client { id, reply },
+            payload: msg,
+        };
+        (message, replies)
+    }
+    pub fn console(msg: String) -> Self {
+        CmdMessage {
+            source: CmdSource::Console,
+            payload: msg,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every escape sequence of the line is complete and the colours are reset at its end.
+    fn assert_balanced(line: &str) {
+        let mut rest = line;
+        while let Some(start) = rest.find('\x1b') {
+            let sequence = &rest[start..];
+            let end = sequence.find('m').expect("unterminated escape sequence");
+            assert!(sequence[2..end]
+                .bytes()
+                .all(|b| b.is_ascii_digit() || b == b';'));
+            rest = &sequence[end + 1..];
+        }
+        if line.contains('\x1b') {
+            assert!(line.trim_end().ends_with("\x1b[0m"), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn wraps_before_colouring() {
+        colored::control::set_override(true);
+        let mut chat = Chat::new(20);
+        chat.set_highlights("bob".to_string(), Vec::new());
+        let nick = "n".repeat(50);
+        chat.add_text(ChatMessage::server(format!(
+            "Nickname changed to {}",
+            highlighted(&nick)
+        )));
+        let mention = format!("hey bob {}", "x".repeat(47));
+        assert!(chat.add_text(ChatMessage::message(
+            MessageKind::Chat,
+            "alice".to_string(),
+            mention.clone()
+        )));
+        let lines = chat.chat.iter().collect::<Vec<&String>>();
+        assert_eq!(lines.len(), 3);
+        for line in lines.iter() {
+            assert_balanced(line);
+        }
+        let plain = lines
+            .iter()
+            .map(|line| strip_ansi(line).trim().to_string())
+            .collect::<Vec<String>>();
+        let text = format!("Nickname changed to {}", nick);
+        assert_eq!(plain[0], format!("[SERVER]: {}", &text[..WRAP_WIDTH]));
+        assert_eq!(plain[1], &text[WRAP_WIDTH..]);
+        assert_eq!(plain[2], format!("[alice]: {}", mention));
+        // the highlight goes on from one line to the next
+        assert!(lines[1].contains(&"n".repeat(10).yellow().to_string()));
+    }
+
+    #[test]
+    fn masks_match_whole_nicks() {
+        assert!(mask_matches("bob", "bob"));
+        assert!(mask_matches("BoB", "bOb"));
+        assert!(!mask_matches("bob", "bobby"));
+        assert!(!mask_matches("bobby", "bob"));
+        assert!(!mask_matches("", "bob"));
+        assert!(mask_matches("", ""));
+    }
+
+    #[test]
+    fn masks_wildcards() {
+        assert!(mask_matches("*", "anyone"));
+        assert!(mask_matches("*", ""));
+        assert!(mask_matches("Anonymous-*", "anonymous-12"));
+        assert!(mask_matches("Anonymous-*", "Anonymous-"));
+        assert!(!mask_matches("Anonymous-*", "Anon-12"));
+        assert!(mask_matches("*bot", "helperbot"));
+        assert!(!mask_matches("*bot", "bots"));
+        assert!(mask_matches("b?b", "bob"));
+        assert!(!mask_matches("b?b", "bb"));
+        assert!(mask_matches("a*b*c", "aXbYbZc"));
+        assert!(!mask_matches("a*b*c", "aXcYb"));
+        // a `*` has to backtrack past partial matches
+        assert!(mask_matches("*ab", "aab"));
+        assert!(mask_matches("**a?*", "xxab"));
+        assert!(!mask_matches("n?ck*", "ñock"));
+        // `?` is one character, not one byte
+        assert!(mask_matches("?ick", "ñick"));
     }
 }
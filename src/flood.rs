@@ -0,0 +1,86 @@
+use std::time::Instant;
+
+use crate::config::FloodConfig;
+
+/// Token bucket holding up to `burst` tokens, refilled at `rate` tokens per second.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+impl TokenBucket {
+    pub fn new(burst: f64) -> Self {
+        TokenBucket {
+            tokens: burst,
+            updated_at: Instant::now(),
+        }
+    }
+    /// Takes a token if one is available, the limits are passed in so a reload applies at once.
+    pub fn try_take(&mut self, burst: f64, rate: f64) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.updated_at = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Traffic {
+    Message,
+    Command,
+}
+
+/// What the session does with a line, escalating while the client keeps flooding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Allow,
+    /// First line over the limit, dropped and the client is told to slow down.
+    Warn,
+    /// Following lines over the limit, silently dropped.
+    Throttle,
+    Disconnect,
+}
+
+/// Per session rate limiter for chat messages and commands.
+#[derive(Debug, Clone)]
+pub struct FloodGuard {
+    messages: TokenBucket,
+    commands: TokenBucket,
+    /// Lines dropped since the client last sent one within its limits.
+    violations: u32,
+}
+impl FloodGuard {
+    pub fn new(config: &FloodConfig) -> Self {
+        FloodGuard {
+            messages: TokenBucket::new(config.burst as f64),
+            commands: TokenBucket::new(config.command_burst as f64),
+            violations: 0,
+        }
+    }
+    pub fn check(&mut self, traffic: Traffic, config: &FloodConfig) -> Verdict {
+        if !config.enabled {
+            return Verdict::Allow;
+        }
+        let allowed = match traffic {
+            Traffic::Message => self.messages.try_take(config.burst as f64, config.rate),
+            Traffic::Command => self
+                .commands
+                .try_take(config.command_burst as f64, config.command_rate),
+        };
+        if allowed {
+            self.violations = 0;
+            return Verdict::Allow;
+        }
+        self.violations += 1;
+        match self.violations {
+            1 => Verdict::Warn,
+            n if n >= config.max_violations => Verdict::Disconnect,
+            _ => Verdict::Throttle,
+        }
+    }
+}
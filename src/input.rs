@@ -0,0 +1,192 @@
+/// Telnet "interpret as command" escape and the commands that matter for framing.
+const IAC: u8 = 255;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const WILL: u8 = 251;
+const DONT: u8 = 254;
+
+/// A complete line read from the client.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    Text(String),
+    /// The line was longer than the limit and has been discarded.
+    TooLong,
+    InvalidUtf8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TelnetState {
+    Data,
+    Iac,
+    /// Waiting for the option byte of a WILL/WONT/DO/DONT negotiation.
+    Option,
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// Splits the client byte stream into lines, keeping at most `max_length` bytes of a partial line.
+///
+/// Bytes are only decoded once the line is complete, so a character split across two reads is
+/// kept intact. Telnet command sequences are dropped from the stream.
+#[derive(Debug, Clone)]
+pub struct LineReader {
+    buffer: Vec<u8>,
+    /// Set once the current line went over the limit, the rest of it is skipped.
+    overflowed: bool,
+    telnet: TelnetState,
+}
+impl LineReader {
+    pub fn new() -> Self {
+        LineReader {
+            buffer: Vec::new(),
+            overflowed: false,
+            telnet: TelnetState::Data,
+        }
+    }
+    /// Returns the lines completed by `data`, the limit is passed in so a reload applies at once.
+    pub fn feed(&mut self, data: &[u8], max_length: usize) -> Vec<Line> {
+        let mut lines = Vec::new();
+        for &byte in data {
+            self.telnet = match (self.telnet, byte) {
+                (TelnetState::Data, IAC) => TelnetState::Iac,
+                (TelnetState::Data, b'\n') => {
+                    lines.push(self.finish_line());
+                    TelnetState::Data
+                }
+                (TelnetState::Data, 0) => TelnetState::Data,
+                (TelnetState::Data, byte) => {
+                    self.push(byte, max_length);
+                    TelnetState::Data
+                }
+                (TelnetState::Iac, IAC) => {
+                    self.push(IAC, max_length);
+                    TelnetState::Data
+                }
+                (TelnetState::Iac, WILL..=DONT) => TelnetState::Option,
+                (TelnetState::Iac, SB) => TelnetState::Subnegotiation,
+                (TelnetState::Iac, _) | (TelnetState::Option, _) => TelnetState::Data,
+                (TelnetState::Subnegotiation, IAC) => TelnetState::SubnegotiationIac,
+                (TelnetState::Subnegotiation, _) => TelnetState::Subnegotiation,
+                (TelnetState::SubnegotiationIac, SE) => TelnetState::Data,
+                (TelnetState::SubnegotiationIac, _) => TelnetState::Subnegotiation,
+            };
+        }
+        lines
+    }
+    fn push(&mut self, byte: u8, max_length: usize) {
+        if self.overflowed {
+            return;
+        }
+        if self.buffer.len() >= max_length {
+            self.overflowed = true;
+            self.buffer = Vec::new();
+            return;
+        }
+        self.buffer.push(byte);
+    }
+    fn finish_line(&mut self) -> Line {
+        if std::mem::take(&mut self.overflowed) {
+            return Line::TooLong;
+        }
+        let mut line = std::mem::take(&mut self.buffer);
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        match String::from_utf8(line) {
+            Ok(text) => Line::Text(text),
+            Err(_) => Line::InvalidUtf8,
+        }
+    }
+}
+impl Default for LineReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(line: &str) -> Line {
+        Line::Text(line.to_string())
+    }
+
+    #[test]
+    fn splits_lines_and_drops_carriage_returns() {
+        let mut reader = LineReader::new();
+        assert_eq!(
+            reader.feed(b"hello\r\nworld\n\r\n", 64),
+            [text("hello"), text("world"), text("")]
+        );
+        assert!(reader.feed(b"partial", 64).is_empty());
+        assert_eq!(reader.feed(b" line\r\n", 64), [text("partial line")]);
+    }
+
+    #[test]
+    fn keeps_characters_split_across_reads() {
+        let mut reader = LineReader::new();
+        let bytes = "héllo €".as_bytes();
+        assert!(reader.feed(&bytes[..2], 64).is_empty());
+        assert!(reader.feed(&bytes[2..bytes.len() - 1], 64).is_empty());
+        assert!(reader.feed(&bytes[bytes.len() - 1..], 64).is_empty());
+        assert_eq!(reader.feed(b"\n", 64), [text("héllo €")]);
+    }
+
+    #[test]
+    fn reports_invalid_utf8() {
+        let mut reader = LineReader::new();
+        assert_eq!(
+            reader.feed(b"\xc3(\nok\n", 64),
+            [Line::InvalidUtf8, text("ok")]
+        );
+    }
+
+    #[test]
+    fn discards_long_lines_and_resets() {
+        let mut reader = LineReader::new();
+        assert_eq!(reader.feed(b"12345\n", 5), [text("12345")]);
+        assert!(reader.feed(b"123456", 5).is_empty());
+        assert!(reader.feed(b"more text", 5).is_empty());
+        assert_eq!(reader.feed(b"\nabc\n", 5), [Line::TooLong, text("abc")]);
+        // the limit is read on each call
+        assert_eq!(reader.feed(b"123456\n", 10), [text("123456")]);
+    }
+
+    #[test]
+    fn drops_telnet_commands() {
+        let mut reader = LineReader::new();
+        // IAC DO ECHO, IAC NOP and IAC WILL NAWS around the text
+        let data = [
+            &[IAC, 253, 1][..],
+            b"he",
+            &[IAC, 241],
+            b"llo",
+            &[IAC, WILL, 31],
+            b"\r\n",
+        ]
+        .concat();
+        assert_eq!(reader.feed(&data, 64), [text("hello")]);
+    }
+
+    #[test]
+    fn drops_subnegotiations_across_reads() {
+        let mut reader = LineReader::new();
+        // IAC SB NAWS 0 80 0 24 IAC SE, with an escaped IAC in the payload
+        assert!(reader.feed(&[b'a', IAC, SB, 31, 0, 80, IAC], 64).is_empty());
+        assert!(reader.feed(&[IAC, 0, 24, b'\n', IAC], 64).is_empty());
+        assert_eq!(reader.feed(&[SE, b'b', b'\n'], 64), [text("ab")]);
+    }
+
+    #[test]
+    fn keeps_escaped_iac_bytes_and_drops_nul() {
+        let mut reader = LineReader::new();
+        // an escaped IAC is a data byte, it counts towards the limit
+        assert_eq!(reader.feed(&[IAC, IAC, b'\n'], 1), [Line::InvalidUtf8]);
+        assert_eq!(
+            reader.feed(&[IAC, IAC, IAC, IAC, b'\n'], 1),
+            [Line::TooLong]
+        );
+        assert_eq!(reader.feed(&[b'a', 0, b'b', b'\n'], 64), [text("ab")]);
+    }
+}
@@ -0,0 +1,247 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::flood::TokenBucket;
+
+/// An address range in CIDR notation, a bare address is a range of one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+impl FromStr for Cidr {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let parsed = IpAddr::from_str(addr).with_context(|| format!("invalid address {}", addr))?;
+        let addr = parsed.to_canonical();
+        let max_prefix = match parsed {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .with_context(|| format!("invalid prefix length {}", prefix))?,
+            None => max_prefix,
+        };
+        // an IPv4-mapped range like `::ffff:10.0.0.0/104` is matched as `10.0.0.0/8`
+        let prefix = match (parsed, addr) {
+            (IpAddr::V6(_), IpAddr::V4(_)) => prefix
+                .checked_sub(96)
+                .with_context(|| format!("invalid prefix length {} for {}", prefix, addr))?,
+            _ => prefix,
+        };
+        // host bits are cleared so `10.1.2.3/8` and `10.0.0.0/8` are the same range
+        let addr = match addr {
+            IpAddr::V4(addr) => {
+                let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+                IpAddr::V4((u32::from(addr) & mask).into())
+            }
+            IpAddr::V6(addr) => {
+                let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+                IpAddr::V6((u128::from(addr) & mask).into())
+            }
+        };
+        Ok(Cidr { addr, prefix })
+    }
+}
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IpBan {
+    /// Banned range in CIDR notation.
+    pub cidr: String,
+    pub reason: String,
+    pub created_at: u64,
+}
+
+/// File backed server wide list of banned address ranges.
+#[derive(Debug, Clone)]
+pub struct IpBanList {
+    path: PathBuf,
+    bans: Vec<(Cidr, IpBan)>,
+}
+impl IpBanList {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut bans = Vec::new();
+        if path.exists() {
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("unable to read ban list {}", path.display()))?;
+            let list: Vec<IpBan> = serde_json::from_str(&content)
+                .with_context(|| format!("unable to parse ban list {}", path.display()))?;
+            for ban in list {
+                bans.push((ban.cidr.parse()?, ban));
+            }
+        }
+        Ok(IpBanList { path, bans })
+    }
+    pub fn save(&self) -> Result<()> {
+        let list = self.bans();
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(&list)?)
+            .with_context(|| format!("unable to write ban list {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+    /// Returns the ban matching the address, if any.
+    pub fn find(&self, ip: IpAddr) -> Option<&IpBan> {
+        self.bans
+            .iter()
+            .find(|(cidr, _)| cidr.contains(ip))
+            .map(|(_, ban)| ban)
+    }
+    pub fn ban(&mut self, cidr: &str, reason: &str) -> Result<Cidr> {
+        let parsed = cidr.parse::<Cidr>()?;
+        if self.bans.iter().any(|(other, _)| *other == parsed) {
+            bail!("{} is already banned", parsed);
+        }
+        let ban = IpBan {
+            cidr: parsed.to_string(),
+            reason: reason.to_string(),
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        };
+        self.bans.push((parsed, ban));
+        self.save()?;
+        Ok(parsed)
+    }
+    pub fn unban(&mut self, cidr: &str) -> Result<()> {
+        let parsed = cidr.parse::<Cidr>()?;
+        let index = self
+            .bans
+            .iter()
+            .position(|(other, _)| *other == parsed)
+            .with_context(|| format!("{} is not banned", parsed))?;
+        self.bans.remove(index);
+        self.save()
+    }
+    pub fn bans(&self) -> Vec<IpBan> {
+        self.bans.iter().map(|(_, ban)| ban.clone()).collect()
+    }
+}
+
+/// Connection attempts per source address, limited with a token bucket each.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectThrottle {
+    buckets: HashMap<IpAddr, (TokenBucket, Instant)>,
+}
+impl ConnectThrottle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Records a connection attempt, returns false when the address is over its limit.
+    pub fn try_connect(&mut self, ip: IpAddr, burst: u32, rate: f64) -> bool {
+        let now = Instant::now();
+        // an idle address has its bucket full again, forgetting it changes nothing
+        let idle = Duration::try_from_secs_f64(burst as f64 / rate).unwrap_or(Duration::MAX);
+        self.buckets.retain(|_, (_, seen)| now - *seen < idle);
+        let (bucket, seen) = self
+            .buckets
+            .entry(ip)
+            .or_insert_with(|| (TokenBucket::new(burst as f64), now));
+        *seen = now;
+        bucket.try_take(burst as f64, rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ipv4_prefix_masks() {
+        let range = cidr("10.1.2.3/8");
+        assert_eq!(range.to_string(), "10.0.0.0/8");
+        assert!(range.contains(ip("10.255.0.1")));
+        assert!(!range.contains(ip("11.0.0.1")));
+        let range = cidr("192.168.1.0/23");
+        assert!(range.contains(ip("192.168.0.7")));
+        assert!(!range.contains(ip("192.168.2.0")));
+        let single = cidr("192.168.1.9");
+        assert_eq!(single.to_string(), "192.168.1.9/32");
+        assert!(single.contains(ip("192.168.1.9")));
+        assert!(!single.contains(ip("192.168.1.8")));
+    }
+
+    #[test]
+    fn ipv6_prefix_masks() {
+        let range = cidr("2001:db8::1/32");
+        assert_eq!(range.to_string(), "2001:db8::/32");
+        assert!(range.contains(ip("2001:db8:ffff::1")));
+        assert!(!range.contains(ip("2001:db9::1")));
+        assert!(!range.contains(ip("10.0.0.1")));
+        assert!(cidr("::1").contains(ip("::1")));
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses() {
+        // clients of a dual stack listener show up as mapped addresses
+        assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.2.3.4")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("::ffff:11.2.3.4")));
+        assert_eq!(cidr("::ffff:10.2.3.4").to_string(), "10.2.3.4/32");
+        let range = cidr("::ffff:10.0.0.0/104");
+        assert_eq!(range, cidr("10.0.0.0/8"));
+        assert!(range.contains(ip("10.2.3.4")));
+        assert!("::ffff:10.0.0.0/95".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn zero_prefix_matches_the_whole_family() {
+        assert!(cidr("0.0.0.0/0").contains(ip("255.255.255.255")));
+        assert!(cidr("1.2.3.4/0").contains(ip("::ffff:8.8.8.8")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(cidr("::/0").contains(ip("2001:db8::1")));
+        assert!(!cidr("::/0").contains(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("::/129".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/-1".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+        assert!("example.com".parse::<Cidr>().is_err());
+    }
+}
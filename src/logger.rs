@@ -0,0 +1,146 @@
+use anyhow::*;
+use chrono::Local;
+use log::{
+    kv::{Key, Value, VisitSource},
+    LevelFilter, Log, Metadata, Record,
+};
+
+use std::{fs::OpenOptions, io::Write, str::FromStr, sync::Mutex};
+
+use crate::config::{LogConfig, LogFormat};
+
+/// Logs a session event with the connection id, peer address and nick of the client.
+macro_rules! session_log {
+    ($lvl:expr, $client:expr, $($arg:tt)+) => {{
+        let (conn_id, peer, nick) = $client.lock().unwrap().log_context();
+        log::log!($lvl, conn_id = conn_id, peer = peer, nick = nick; $($arg)+)
+    }};
+}
+pub(crate) use session_log;
+
+pub struct Logger {
+    level: LevelFilter,
+    /// Module path prefixes with their own level, longest prefix wins.
+    filters: Vec<(String, LevelFilter)>,
+    format: LogFormat,
+    output: Mutex<Box<dyn Write + Send>>,
+}
+impl Logger {
+    pub fn new(config: &LogConfig) -> Result<Self> {
+        let level = parse_level(&config.level)?;
+        let mut filters = Vec::new();
+        for (module, level) in config.filters.iter() {
+            filters.push((module.clone(), parse_level(level)?));
+        }
+        filters.sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+        let output: Box<dyn Write + Send> = match &config.file {
+            Some(path) => Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("unable to open log file {}", path))?,
+            ),
+            None => Box::new(std::io::stderr()),
+        };
+        Ok(Logger {
+            level,
+            filters,
+            format: config.format,
+            output: Mutex::new(output),
+        })
+    }
+    /// Installs the logger as the global `log` backend.
+    pub fn init(config: &LogConfig) -> Result<()> {
+        let logger = Logger::new(config)?;
+        let max_level = logger
+            .filters
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(logger.level, std::cmp::max);
+        log::set_boxed_logger(Box::new(logger))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.filters
+            .iter()
+            .find(|(module, _)| target.starts_with(module.as_str()))
+            .map(|(_, level)| *level)
+            .unwrap_or(self.level)
+    }
+    fn format_text(&self, record: &Record) -> String {
+        let mut line = format!(
+            "{} {:<5} {}: {}",
+            Local::now().format("%Y-%m-%dT%H:%M:%S%.3f%:z"),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        for (key, value) in collect_fields(record) {
+            let value = match value {
+                serde_json::Value::String(value) => value,
+                value => value.to_string(),
+            };
+            line += &format!(" {}={}", key, value);
+        }
+        line
+    }
+    fn format_json(&self, record: &Record) -> String {
+        let mut object = serde_json::Map::new();
+        object.insert("timestamp".to_string(), Local::now().to_rfc3339().into());
+        object.insert("level".to_string(), record.level().as_str().into());
+        object.insert("target".to_string(), record.target().into());
+        object.insert("message".to_string(), record.args().to_string().into());
+        for (key, value) in collect_fields(record) {
+            object.insert(key, value);
+        }
+        serde_json::Value::Object(object).to_string()
+    }
+}
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = match self.format {
+            LogFormat::Text => self.format_text(record),
+            LogFormat::Json => self.format_json(record),
+        };
+        let mut output = self.output.lock().unwrap();
+        let _ = writeln!(output, "{}", line);
+    }
+    fn flush(&self) {
+        let _ = self.output.lock().unwrap().flush();
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter> {
+    LevelFilter::from_str(level).with_context(|| format!("invalid log level {}", level))
+}
+
+/// Key-value pairs attached to the record, numbers and booleans keep their type.
+fn collect_fields(record: &Record) -> Vec<(String, serde_json::Value)> {
+    struct Fields(Vec<(String, serde_json::Value)>);
+    impl<'kvs> VisitSource<'kvs> for Fields {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+            let value = if let Some(value) = value.to_u64() {
+                value.into()
+            } else if let Some(value) = value.to_i64() {
+                value.into()
+            } else if let Some(value) = value.to_bool() {
+                value.into()
+            } else {
+                value.to_string().into()
+            };
+            self.0.push((key.to_string(), value));
+            std::result::Result::Ok(())
+        }
+    }
+    let mut fields = Fields(Vec::new());
+    let _ = record.key_values().visit(&mut fields);
+    fields.0
+}
@@ -1,14 +1,28 @@
+mod accounts;
+mod api;
+mod commands;
+mod config;
+mod console;
 mod defs;
+mod flood;
+mod input;
+mod limits;
+mod logger;
+mod memos;
+mod metrics;
+mod search;
+mod snapshot;
+mod storage;
 
 mod terminal_ansi;
-use colored::Colorize;
 use terminal_ansi::*;
 
 use std::io::{self, Read, Write};
 
 use std::{
-    net::TcpListener,
+    net::{IpAddr, TcpListener, TcpStream},
     thread::{self},
+    time::{Duration, Instant},
 };
 
 macro_rules! spawn_thread {
@@ -19,13 +33,35 @@ macro_rules! spawn_thread {
     };
 }
 use anyhow::Result;
+use api::handle_api;
+use config::{Config, DEFAULT_CONFIG_PATH};
+use console::{handle_console, run_console_command};
 use defs::*;
-use terminal_ansi::{formatted_terminal, update_prompt};
+use flood::{FloodGuard, Traffic, Verdict};
+use input::{Line, LineReader};
+use limits::ConnectThrottle;
+use log::Level;
+use logger::{session_log, Logger};
+use metrics::handle_metrics;
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    iterator::Signals,
+};
+use terminal_ansi::update_prompt;
 
 fn main() -> Result<()> {
-    let server_engine = ServerEngine::new();
+    let config_path = std::env::args()
+        .nth(1)
+        .unwrap_or(DEFAULT_CONFIG_PATH.to_string());
+    let config = Config::load(config_path)?;
+    Logger::init(&config.log)?;
+    let server_engine = ServerEngine::new(config)?;
     let mut threads_handle = Vec::new();
 
+    let clone_server_engine = server_engine.clone();
+    let handler = spawn_thread!("signals", { handle_signals(clone_server_engine) });
+    threads_handle.push(handler);
+
     let clone_server_engine = server_engine.clone();
     let handler = spawn_thread!("main_server", { main_server(clone_server_engine) });
     threads_handle.push(handler);
@@ -36,54 +72,216 @@ fn main() -> Result<()> {
     });
     threads_handle.push(handler);
 
+    let clone_server_engine = server_engine.clone();
+    let handler = spawn_thread!("console", { handle_console(clone_server_engine) });
+    threads_handle.push(handler);
+
+    let clone_server_engine = server_engine.clone();
+    let handler = spawn_thread!("snapshot", { handle_snapshots(clone_server_engine) });
+    threads_handle.push(handler);
+
+    if server_engine.config.lock().unwrap().metrics.enabled {
+        let clone_server_engine = server_engine.clone();
+        let handler = spawn_thread!("metrics", { handle_metrics(clone_server_engine) });
+        threads_handle.push(handler);
+    }
+
+    if server_engine.config.lock().unwrap().api.enabled {
+        let clone_server_engine = server_engine.clone();
+        let handler = spawn_thread!("api", { handle_api(clone_server_engine) });
+        threads_handle.push(handler);
+    }
+
     for handle in threads_handle {
-        let join = handle.join();
-        println!("Thread joined: {:?}", join)
+        let name = handle.thread().name().unwrap_or_default().to_string();
+        match handle.join() {
+            Ok(Ok(_)) => log::info!("thread {} finished", name),
+            Ok(Err(e)) => log::error!("thread {} failed: {:#}", name, e),
+            Err(_) => log::error!("thread {} panicked", name),
+        }
     }
     Ok(())
 }
 
 fn main_server(server: ServerEngine) -> Result<()> {
-    let clients = server.clients;
-    let channels = server.channels;
-    let service_bus = server.service_bus;
-    let broadcast_channel = Channel::new(0, "broadcast".to_string()).as_arc_mut();
-    channels.lock().unwrap().add_channel(broadcast_channel)?;
+    let clients = server.clients.clone();
+    let channels = server.channels.clone();
+    let config = server.config.lock().unwrap().server.clone();
+    let mut throttle = ConnectThrottle::new();
 
     loop {
-        let tcp_server = TcpListener::bind("0.0.0.0:2121")?;
+        let tcp_server = TcpListener::bind(&config.bind)?;
         for stream in tcp_server.incoming() {
-            let incoming_stream = stream?;
-            incoming_stream.set_nonblocking(true)?;
-            let id = clients.lock().unwrap().list.len();
-            let nick = format!("Anonymous-{}", id);
+            // a failed connection must not stop the server from accepting the next ones
+            let incoming_stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("unable to accept connection: {}", e);
+                    continue;
+                }
+            };
+            let peer = match incoming_stream.peer_addr() {
+                Ok(peer) => peer,
+                Err(e) => {
+                    log::warn!("connection closed before being accepted: {}", e);
+                    continue;
+                }
+            };
+            if let Some((reason, message)) = check_admission(&server, &mut throttle, peer.ip()) {
+                log::info!(peer = peer; "connection rejected: {}", reason);
+                server.metrics.count_rejection(reason);
+                reject(incoming_stream, &message);
+                continue;
+            }
+            if let Err(e) = incoming_stream.set_nonblocking(true) {
+                log::warn!(peer = peer; "unable to set up connection: {}", e);
+                continue;
+            }
+            let id = clients.lock().unwrap().next_id();
+            let nick = anonymous_nick(id);
+            let channel = channels.lock().unwrap().get_default_channel()?;
             let mut client =
-                Client::new(id, nick, 20, channels.lock().unwrap().get_default_channel());
-            client.terminal.header = "Rust Coded IcsBoyX ChatHole server".to_string();
+                Client::new(id, peer, nick.clone(), config.chat_lines, channel.clone());
+            client.terminal.header = config.header.clone();
+            client.subscribe_to_channel()?;
             let client = client.as_arc_mut();
 
-            let stream = ClientStream::new(incoming_stream, client.clone(), service_bus.clone());
-            clients.lock().unwrap().list.push(client.clone());
+            let stream = ClientStream::new(incoming_stream, client.clone(), server.clone());
+            clients.lock().unwrap().add_client(client.clone())?;
+            let name = channel.lock().unwrap().name.clone();
+            server.announce(
+                &channel,
+                &client,
+                Event::Join {
+                    nick,
+                    channel: name,
+                },
+            )?;
 
-            channels.lock().unwrap().list[0]
-                .lock()
-                .unwrap()
-                .add_subscriber(client.lock().unwrap().rx.clone())?;
             spawn_thread!(format!("client-{id}"), { handle_client(stream) });
         }
     }
 }
 
+/// Returns the reason and the message to turn the address away with, if it may not connect.
+fn check_admission(
+    server: &ServerEngine,
+    throttle: &mut ConnectThrottle,
+    ip: IpAddr,
+) -> Option<(&'static str, String)> {
+    let ip = ip.to_canonical();
+    if let Some(ban) = server.ip_bans.lock().unwrap().find(ip) {
+        return Some((
+            "banned",
+            format!("You are banned from this server: {}", ban.reason),
+        ));
+    }
+    let limits = server.config.lock().unwrap().limits.clone();
+    if !throttle.try_connect(ip, limits.connect_burst, limits.connect_rate) {
+        return Some((
+            "rate",
+            "You are connecting too often, please try again later".to_string(),
+        ));
+    }
+    let clients = server.clients.lock().unwrap().list.clone();
+    if clients.len() >= limits.max_clients {
+        return Some(("full", "Server is full, please try again later".to_string()));
+    }
+    let from_ip = clients
+        .iter()
+        .filter(|client| client.lock().unwrap().peer.ip().to_canonical() == ip)
+        .count();
+    if from_ip >= limits.max_per_ip {
+        return Some((
+            "per_ip",
+            "Too many connections from your address, please try again later".to_string(),
+        ));
+    }
+    None
+}
+
+/// Writes the message to a connection that is not allowed in and closes it.
+fn reject(mut stream: TcpStream, message: &str) {
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+    let _ = stream.write_all(format!("{}\r\n", message).as_bytes());
+    let _ = stream.shutdown(std::net::Shutdown::Both);
+}
+
+/// Shuts down on SIGINT/SIGTERM and reloads the configuration on SIGHUP.
+fn handle_signals(server: ServerEngine) -> Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+    for signal in signals.forever() {
+        match signal {
+            SIGHUP => match server.reload_config() {
+                Ok(path) => log::info!("configuration reloaded from {}", path.display()),
+                Err(e) => log::error!("unable to reload configuration: {:#}", e),
+            },
+            _ => shutdown(&server),
+        }
+    }
+    Ok(())
+}
+
+/// Disconnects every client restoring its terminal, flushes logs and snapshot, then exits.
+pub fn shutdown(server: &ServerEngine) -> ! {
+    log::info!("shutting down");
+    let _ = server.broadcast(ChatMessage::server(
+        "Server is shutting down, see you later alligator!".to_string(),
+    ));
+    server.begin_shutdown();
+    let timeout = Duration::from_secs(server.config.lock().unwrap().server.shutdown_timeout);
+    let started_at = Instant::now();
+    while !server.clients.lock().unwrap().is_empty() && started_at.elapsed() < timeout {
+        thread::sleep(Duration::from_millis(10));
+    }
+    if let Err(e) = server.flush() {
+        log::error!("unable to flush server state: {}", e);
+    }
+    std::process::exit(0)
+}
+
+/// Saves a snapshot of the server state every `snapshot.interval` seconds.
+fn handle_snapshots(server: ServerEngine) -> Result<()> {
+    let interval = server.config.lock().unwrap().snapshot.interval;
+    if interval == 0 {
+        log::info!("periodic snapshots disabled, the snapshot is only saved on shutdown");
+        return Ok(());
+    }
+    loop {
+        thread::sleep(Duration::from_secs(interval));
+        if let Err(e) = server.save_snapshot() {
+            log::error!("unable to save snapshot: {}", e);
+        }
+    }
+}
+
 fn handle_client(mut session: ClientStream) -> Result<()> {
-    let mut full_buffer = Vec::new();
-    let _ = session.stream.write(init_terminal().as_bytes())?;
-    let _ = session
-        .stream
-        .write(formatted_terminal(&mut session.client.lock().unwrap().terminal).as_bytes())?;
+    session_log!(Level::Info, session.client, "client connected");
+    let result = client_loop(&mut session);
+    let reason = match &result {
+        Ok(reason) => {
+            session_log!(Level::Info, session.client, "client disconnected");
+            reason.clone()
+        }
+        Err(e) => {
+            session_log!(Level::Warn, session.client, "client disconnected: {:#}", e);
+            Some("connection lost".to_string())
+        }
+    };
+    session.cleanup(reason)?;
+    result.map(|_| ())
+}
+
+/// Serves the client until it leaves, returns the quit reason shown to its channel.
+fn client_loop(session: &mut ClientStream) -> Result<Option<String>> {
+    let mut reader = LineReader::new();
+    let mut flood_guard = FloodGuard::new(&session.server.config.lock().unwrap().flood);
+    let mut probed_at = Instant::now();
+    session.write(&init_terminal())?;
+    session.render()?;
 
     loop {
         let mut buffer = [0; 1024];
-        let mut payload: String;
         match session.stream.read(&mut buffer) {
             Ok(0) => {
                 session
@@ -91,103 +289,319 @@ fn handle_client(mut session: ClientStream) -> Result<()> {
                     .lock()
                     .unwrap()
                     .terminal
-                    .add_message(ChatMessage::new(
-                        "SERVER".blue().bold().to_string(),
-                        "See you later alligator!".to_string(),
-                    ));
-                let _ = session.stream.write(restore_terminal().as_bytes())?;
+                    .add_message(ChatMessage::server("See you later alligator!".to_string()));
+                session.write(&restore_terminal())?;
                 session.shutdown()?;
-                break;
+                return Ok(Some("connection closed".to_string()));
             }
             Ok(n) => {
-                println!("read {:?} bytes", &buffer[..n]);
+                session_log!(Level::Trace, session.client, "read {} bytes", n);
+                session.server.metrics.add_bytes_received(n);
                 if is_ctrl_c(&buffer[..n]) {
                     session
                         .client
                         .lock()
                         .unwrap()
                         .terminal
-                        .add_message(ChatMessage::new(
-                            "SERVER".blue().bold().to_string(),
-                            "See you later alligator!".to_string(),
-                        ));
-                    let _ = session.stream.write(restore_terminal().as_bytes())?;
+                        .add_message(ChatMessage::server("See you later alligator!".to_string()));
+                    session.write(&restore_terminal())?;
                     session.shutdown()?;
-                    break;
-                }
-                if Some(&b'\n') == buffer.get(n - 1) {
-                    full_buffer.extend_from_slice(&buffer[..n]);
-                    payload = String::from_utf8_lossy(&full_buffer).to_string();
-                    payload = payload
-                        .trim_end_matches('\n')
-                        .trim_end_matches('\r')
-                        .to_string();
-                    full_buffer.clear();
-                } else {
-                    full_buffer.extend_from_slice(&buffer[..n]);
-                    continue;
-                }
-
-                if payload.is_empty() || payload == "\r" {
-                    let _ = session.stream.write(
-                        update_prompt(&mut session.client.lock().unwrap().terminal).as_bytes(),
-                    )?;
-                    continue;
+                    return Ok(None);
                 }
-
-                if payload.starts_with('/') {
-                    let id = session.client.lock().unwrap().id;
-                    session
-                        .service_bus
-                        .lock()
-                        .unwrap()
-                        .push_back(CmdMessage::new(id, payload.clone()))?;
-                    thread::sleep(std::time::Duration::from_millis(100));
-                    let _ = session.stream.write(
-                        update_prompt(&mut session.client.lock().unwrap().terminal).as_bytes(),
-                    )?;
-                    continue;
-                }
-                let _ = session.stream.write(
-                    update_prompt(&mut session.client.lock().unwrap().terminal).as_bytes(),
-                )?;
-
-                // ########################################################## //
-                let mut receivers = session
-                    .client
-                    .lock()
-                    .unwrap()
-                    .channel
-                    .lock()
-                    .unwrap()
-                    .get_all_subscribers();
-
-                for receiver in receivers.iter_mut() {
-                    let nick = session.client.lock().unwrap().nick.clone();
-                    receiver.push_back(ChatMessage::new(nick, payload.clone()))?;
+                let max_length = session.server.config.lock().unwrap().server.max_line_length;
+                for line in reader.feed(&buffer[..n], max_length) {
+                    handle_line(session, &mut flood_guard, line, max_length)?;
                 }
             }
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
             Err(e) => {
-                println!("Error: {}", e);
-                break;
+                session_log!(Level::Warn, session.client, "read error: {}", e);
+                return Ok(Some("connection lost".to_string()));
             }
         }
 
-        while !session.client.lock().unwrap().rx.is_empty() {
-            let message = session.client.lock().unwrap().rx.pop_front().unwrap();
-            session.client.lock().unwrap().terminal.add_message(message);
-            let _ = session.stream.write(
-                formatted_terminal(&mut session.client.lock().unwrap().terminal).as_bytes(),
-            )?;
+        check_idle(session, &mut probed_at)?;
+        let (received, mentioned) = {
+            let mut client = session.client.lock().unwrap();
+            let (mut received, mut mentioned) = (false, false);
+            while let Some(message) = client.rx.pop_front() {
+                mentioned |= client.terminal.add_message(message);
+                received = true;
+            }
+            (received, mentioned)
+        };
+        if received {
+            session.render()?;
+        }
+        if mentioned {
+            session.write(&AnsiControlCodes.bell())?;
+        }
+        let disconnect = session.client.lock().unwrap().disconnect.take();
+        if let Some(reason) = disconnect {
+            session_log!(Level::Info, session.client, "disconnecting: {}", reason);
+            session.write(&restore_terminal())?;
+            session.write(&format!("{}\r\n", reason))?;
+            session.shutdown()?;
+            return Ok(Some(reason));
+        }
+        if session.server.is_shutting_down() {
+            session.write(&restore_terminal())?;
+            session.write("Server is shutting down, see you later alligator!\r\n")?;
+            session.shutdown()?;
+            return Ok(None);
         }
         thread::sleep(std::time::Duration::from_millis(10));
     }
+}
 
+/// Telnet "interpret as command" followed by "no operation", ignored by the client.
+const TELNET_NOP: [u8; 2] = [255, 241];
+
+/// Marks the client away or disconnects it after a while without input, and probes the
+/// connection so a dead peer makes the write fail.
+fn check_idle(session: &mut ClientStream, probed_at: &mut Instant) -> Result<()> {
+    let idle = session.server.config.lock().unwrap().idle.clone();
+    if idle.keepalive_interval > 0
+        && probed_at.elapsed() >= Duration::from_secs(idle.keepalive_interval)
+    {
+        session.write_bytes(&TELNET_NOP)?;
+        *probed_at = Instant::now();
+    }
+    let mut client = session.client.lock().unwrap();
+    let idle_for = client.last_activity.elapsed();
+    if idle.timeout > 0 && idle_for >= Duration::from_secs(idle.timeout) {
+        if client.disconnect.is_none() {
+            client.disconnect = Some(format!(
+                "Disconnected after {} of inactivity",
+                format_duration(idle_for)
+            ));
+        }
+        return Ok(());
+    }
+    if idle.away_after > 0
+        && client.away.is_none()
+        && idle_for >= Duration::from_secs(idle.away_after)
+    {
+        client.away = Some(Away::new("idle".to_string(), true));
+        client.rx.push_back(ChatMessage::server(format!(
+            "You have been marked as away after {} of inactivity",
+            format_duration(idle_for)
+        )))?;
+    }
     Ok(())
 }
 
+fn format_duration(duration: Duration) -> String {
+    match duration.as_secs() {
+        seconds if seconds < 60 => format!("{} seconds", seconds),
+        seconds if seconds < 3600 => format!("{} minutes", seconds / 60),
+        seconds => format!("{} hours", seconds / 3600),
+    }
+}
+
+/// Lines telling the client it is no longer away and what it missed.
+fn welcome_back(away: &Away) -> Vec<String> {
+    let mut lines = vec![format!(
+        "You are no longer marked as away, you were away for {}",
+        format_duration(away.since.elapsed())
+    )];
+    lines.extend(away.digest());
+    lines
+}
+
+/// Runs a command or publishes a message for a complete line read from the client.
+fn handle_line(
+    session: &mut ClientStream,
+    flood_guard: &mut FloodGuard,
+    line: Line,
+    max_length: usize,
+) -> Result<()> {
+    let away = session.client.lock().unwrap().touch();
+    if let Some(away) = away {
+        for line in welcome_back(&away) {
+            reply(&session.client, line)?;
+        }
+    }
+    let payload = match line {
+        Line::Text(payload) => payload,
+        Line::TooLong => {
+            session_log!(Level::Debug, session.client, "line too long discarded");
+            session
+                .client
+                .lock()
+                .unwrap()
+                .rx
+                .push_back(ChatMessage::error(format!(
+                    "Input Error: line longer than {} bytes, it was discarded",
+                    max_length
+                )))?;
+            let prompt = update_prompt(&mut session.client.lock().unwrap().terminal);
+            return session.write(&prompt);
+        }
+        Line::InvalidUtf8 => {
+            session
+                .client
+                .lock()
+                .unwrap()
+                .rx
+                .push_back(ChatMessage::error(
+                    "Input Error: line is not valid UTF-8, it was discarded".to_string(),
+                ))?;
+            let prompt = update_prompt(&mut session.client.lock().unwrap().terminal);
+            return session.write(&prompt);
+        }
+    };
+
+    if payload.is_empty() {
+        let prompt = update_prompt(&mut session.client.lock().unwrap().terminal);
+        return session.write(&prompt);
+    }
+
+    let traffic = match payload.starts_with('/') {
+        true => Traffic::Command,
+        false => Traffic::Message,
+    };
+    if !admit(session, flood_guard, traffic)? {
+        let prompt = update_prompt(&mut session.client.lock().unwrap().terminal);
+        return session.write(&prompt);
+    }
+
+    if payload.starts_with('/') {
+        run_command(session, payload)?;
+        let prompt = update_prompt(&mut session.client.lock().unwrap().terminal);
+        return session.write(&prompt);
+    }
+    let prompt = update_prompt(&mut session.client.lock().unwrap().terminal);
+    session.write(&prompt)?;
+
+    let (channel, nick, account) = {
+        let client = session.client.lock().unwrap();
+        (
+            client.channel.clone(),
+            client.nick.clone(),
+            client.account.clone(),
+        )
+    };
+    let wait = channel
+        .lock()
+        .unwrap()
+        .slow_mode_wait(&nick, account.as_deref());
+    if let Some(wait) = wait {
+        return reply(
+            &session.client,
+            format!(
+                "Slow mode: wait {} seconds before sending another message",
+                wait
+            ),
+        );
+    }
+    session.server.publish(
+        &channel,
+        ChatMessage::message(MessageKind::Chat, nick, payload),
+    )
+}
+
+/// Longest wait for the service bus to answer a command.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends a command to the service bus and renders its whole reply in a single frame, after the
+/// messages received before it.
+fn run_command(session: &mut ClientStream, payload: String) -> Result<()> {
+    let id = session.client.lock().unwrap().id;
+    let (message, replies) = CmdMessage::new(id, payload);
+    session
+        .server
+        .service_bus
+        .lock()
+        .unwrap()
+        .push_back(message)?;
+    let replies = match replies.recv_timeout(COMMAND_TIMEOUT) {
+        Ok(replies) => replies,
+        Err(e) => {
+            session_log!(Level::Warn, session.client, "command not answered: {}", e);
+            vec![ChatMessage::error(
+                "Command Error: the server did not answer, try again".to_string(),
+            )]
+        }
+    };
+    let mut mentioned = false;
+    {
+        let mut client = session.client.lock().unwrap();
+        while let Some(message) = client.rx.pop_front() {
+            mentioned |= client.terminal.add_message(message);
+        }
+        for reply in replies {
+            client.terminal.add_message(reply);
+        }
+    }
+    session.render()?;
+    if mentioned {
+        session.write(&AnsiControlCodes.bell())?;
+    }
+    Ok(())
+}
+
+/// Applies the flood limits to a line, returns whether it may be processed.
+fn admit(session: &mut ClientStream, guard: &mut FloodGuard, traffic: Traffic) -> Result<bool> {
+    let config = session.server.config.lock().unwrap().flood.clone();
+    match guard.check(traffic, &config) {
+        Verdict::Allow => return Ok(true),
+        Verdict::Warn => {
+            session_log!(Level::Warn, session.client, "flood limit reached");
+            reply(
+                &session.client,
+                "You are sending too fast, slow down or you will be disconnected".to_string(),
+            )?;
+        }
+        Verdict::Throttle => {}
+        Verdict::Disconnect => {
+            session.client.lock().unwrap().disconnect =
+                Some("Disconnected for flooding".to_string());
+        }
+    }
+    Ok(false)
+}
+
 // ############################################################################################# //
+fn reply(client: &ArcMut<Client>, msg: String) -> Result<()> {
+    client
+        .lock()
+        .unwrap()
+        .rx
+        .push_back(ChatMessage::server(msg))
+}
+
+/// Renames clients holding a registered nick without being logged in once the grace period expires.
+fn enforce_registered_nicks(server_engine: &ServerEngine) -> Result<()> {
+    let grace_period =
+        Duration::from_secs(server_engine.config.lock().unwrap().accounts.grace_period);
+    let clients = server_engine.clients.lock().unwrap().list.clone();
+    for handle in clients {
+        let mut client = handle.lock().unwrap();
+        if client.is_identified() || client.nick_changed_at.elapsed() < grace_period {
+            continue;
+        }
+        if !server_engine
+            .users
+            .lock()
+            .unwrap()
+            .is_registered(&client.nick)
+        {
+            continue;
+        }
+        let nick = client.nick.clone();
+        let anonymous = anonymous_nick(client.id);
+        client.rx.push_back(ChatMessage::server(format!(
+            "{} is registered and you did not log in, you are now known as {}",
+            highlighted(nick),
+            highlighted(&anonymous)
+        )))?;
+        drop(client);
+        server_engine.change_nick(&handle, anonymous)?;
+    }
+    Ok(())
+}
+
 pub fn is_ctrl_c(payload: &[u8]) -> bool {
     let target_sequence: Vec<&[u8]> = vec![&[255, 244, 255, 253, 6], &[3]];
     if target_sequence.contains(&payload) {
@@ -199,141 +613,56 @@ pub fn is_ctrl_c(payload: &[u8]) -> bool {
 pub fn handle_service_bus(server_engine: ServerEngine) -> Result<()> {
     let mut channels_qt = server_engine.channels.lock().unwrap().len();
     let mut clients_qt = server_engine.clients.lock().unwrap().len();
+    let mut nicks_checked_at = Instant::now();
 
     loop {
+        if nicks_checked_at.elapsed() >= Duration::from_secs(1) {
+            enforce_registered_nicks(&server_engine)?;
+            nicks_checked_at = Instant::now();
+        }
         if server_engine.channels.lock().unwrap().len() != channels_qt {
             channels_qt = server_engine.channels.lock().unwrap().len();
-            println!("Channels: {}", channels_qt);
-            println!(
-                "List of channels: {:?}",
-                server_engine.channels.lock().unwrap().list
-            );
+            let names = server_engine
+                .channels
+                .lock()
+                .unwrap()
+                .list
+                .iter()
+                .map(|channel| channel.lock().unwrap().name.clone())
+                .collect::<Vec<String>>();
+            log::debug!(channels = channels_qt; "channel list changed: {}", names.join(", "));
         }
         if server_engine.clients.lock().unwrap().len() != clients_qt {
             clients_qt = server_engine.clients.lock().unwrap().len();
-            println!("Clients: {}", clients_qt);
+            log::debug!(clients = clients_qt; "client list changed");
         }
-        while server_engine.service_bus.lock().unwrap().len() > 0 {
+        while !server_engine.service_bus.lock().unwrap().is_empty() {
             let message = server_engine
                 .service_bus
                 .lock()
                 .unwrap()
                 .pop_front()
                 .unwrap();
-            let id = message.id;
-
-            let command = message.payload;
-            let command = command.trim_start_matches('/');
-            let command = command.splitn(3, ' ').collect::<Vec<&str>>();
-            let client = server_engine.clients.lock().unwrap().get_client(id);
-            match command[0].to_lowercase().as_str() {
-                "nick" => {
-                    if command.len() < 2 {
-                        client.lock().unwrap().rx.push_back(ChatMessage::new(
-                            "SERVER".blue().bold().to_string(),
-                            format!("Command Error: {} is required", "/nick <nick>".yellow()),
-                        ))?;
-                        continue;
+            let (id, reply) = match message.source {
+                CmdSource::Client { id, reply } => (id, reply),
+                CmdSource::Console => {
+                    if let Err(e) = run_console_command(&server_engine, &message.payload) {
+                        println!("Error: {}", unhighlighted(&format!("{:#}", e)));
                     }
-                    client.lock().unwrap().nick = command[1].to_string();
-                    client.lock().unwrap().rx.push_back(ChatMessage::new(
-                        "SERVER".blue().bold().to_string(),
-                        format!(
-                            "Command Success: Nickname changed to {}",
-                            command[1].yellow()
-                        ),
-                    ))?;
+                    continue;
                 }
-                "join" => {
-                    if command.len() < 2 {
-                        client.lock().unwrap().rx.push_back(ChatMessage::new(
-                            "SERVER".blue().bold().to_string(),
-                            format!("Command Error: {} is required", "/join <channel>".yellow()),
-                        ))?;
-                        continue;
-                    }
+            };
 
-                    let new_channel = server_engine
-                        .channels
-                        .lock()
-                        .unwrap()
-                        .clone()
-                        .get_channel(command[1].to_string())
-                        .clone();
-
-                    if let Some(channel) = new_channel {
-                        client.lock().unwrap().unsubscribe_from_channel()?;
-                        client.lock().unwrap().channel = channel.clone();
-                        client.lock().unwrap().subscribe_to_channel()?;
-
-                        client.lock().unwrap().rx.push_back(ChatMessage::new(
-                            "SERVER".blue().bold().to_string(),
-                            format!("Command Success: Joined {}", command[1].yellow()),
-                        ))?;
-                    } else {
-                        let new_channel = Channel::new(
-                            server_engine.channels.lock().unwrap().len(),
-                            command[1].to_string(),
-                        )
-                        .as_arc_mut();
-                        server_engine
-                            .channels
-                            .lock()
-                            .unwrap()
-                            .add_channel(new_channel.clone())?;
-                        client.lock().unwrap().unsubscribe_from_channel()?;
-                        client.lock().unwrap().channel = new_channel.clone();
-                        client.lock().unwrap().subscribe_to_channel()?;
-
-                        client.lock().unwrap().rx.push_back(ChatMessage::new(
-                            "SERVER".blue().bold().to_string(),
-                            format!("Command Success: Joined {}", command[1].yellow()),
-                        ))?;
-                        continue;
-                    }
-                }
-                "list" => {
-                    let channels = server_engine.channels.lock().unwrap().list.clone();
-                    let channels = channels.iter().cloned();
-
-                    for channel in channels {
-                        let name = channel.lock().unwrap().name.clone();
-                        let subscribers = channel.lock().unwrap().subscribers.len();
-                        client.lock().unwrap().rx.push_back(ChatMessage::new(
-                            "SERVER".blue().bold().to_string(),
-                            format!(
-                                "channel: {} users: {}",
-                                name.yellow(),
-                                subscribers.to_string().yellow()
-                            ),
-                        ))?;
-                    }
-                }
-                "help" => {
-                    let commands = [
-                        "/nick <nick>".yellow(),
-                        "/join <channel>".yellow(),
-                        "/list".yellow(),
-                        "/help".yellow(),
-                    ];
-
-                    for command in commands.iter() {
-                        client.lock().unwrap().rx.push_back(ChatMessage::new(
-                            "SERVER".blue().bold().to_string(),
-                            command.to_string(),
-                        ))?;
-                    }
-                }
-                _ => {
-                    client.lock().unwrap().rx.push_back(ChatMessage::new(
-                        "SERVER".blue().bold().to_string(),
-                        format!(
-                            "Command Error: {} is not a valid command",
-                            command[0].yellow()
-                        ),
-                    ))?;
-                }
-            }
+            let client = match server_engine.clients.lock().unwrap().get_client(id) {
+                Some(client) => client,
+                None => continue,
+            };
+            let replies =
+                server_engine
+                    .commands
+                    .dispatch(&server_engine, &client, &message.payload);
+            // the session is gone when nobody is waiting for the reply
+            let _ = reply.send(replies);
         }
         thread::sleep(std::time::Duration::from_millis(10));
     }
@@ -0,0 +1,145 @@
+use anyhow::*;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use std::path::{Path, PathBuf};
+
+use crate::config::MemosConfig;
+
+/// A note left for a registered nick, kept until its recipient deletes it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Memo {
+    /// Account that sent the memo.
+    pub from: String,
+    /// Account the memo is for.
+    pub to: String,
+    pub text: String,
+    pub sent_at: DateTime<Local>,
+    pub read: bool,
+}
+
+/// File backed memo store, accounts are matched case insensitively.
+///
+/// Memos are numbered per recipient from 1, oldest first.
+#[derive(Debug, Clone)]
+pub struct MemoStore {
+    path: PathBuf,
+    memos: Vec<Memo>,
+}
+impl MemoStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut memos = Vec::new();
+        if path.exists() {
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("unable to read memo store {}", path.display()))?;
+            memos = serde_json::from_str(&content)
+                .with_context(|| format!("unable to parse memo store {}", path.display()))?;
+        }
+        Ok(MemoStore { path, memos })
+    }
+    pub fn save(&self) -> Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(&self.memos)?)
+            .with_context(|| format!("unable to write memo store {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+    /// Stores a memo unless the recipient box is full or the sender has too many unread memos.
+    pub fn send(&mut self, from: &str, to: &str, text: &str, config: &MemosConfig) -> Result<()> {
+        if self.inbox(to).len() >= config.max_per_user {
+            bail!("the memo box of {} is full", to);
+        }
+        let waiting = self
+            .memos
+            .iter()
+            .filter(|memo| !memo.read && memo.from.eq_ignore_ascii_case(from))
+            .count();
+        if waiting >= config.max_unread_sent {
+            bail!(
+                "{} of your memos are still unread, wait until they are read",
+                waiting
+            );
+        }
+        self.memos.push(Memo {
+            from: from.to_string(),
+            to: to.to_string(),
+            text: text.to_string(),
+            sent_at: Local::now(),
+            read: false,
+        });
+        self.save()
+    }
+    /// Memos of the account, oldest first.
+    pub fn inbox(&self, account: &str) -> Vec<&Memo> {
+        self.memos
+            .iter()
+            .filter(|memo| memo.to.eq_ignore_ascii_case(account))
+            .collect()
+    }
+    pub fn unread(&self, account: &str) -> usize {
+        self.inbox(account).iter().filter(|memo| !memo.read).count()
+    }
+    /// Returns the memos with the given numbers, all the unread ones when `number` is `None`,
+    /// and marks them as read.
+    pub fn read(&mut self, account: &str, number: Option<usize>) -> Result<Vec<(usize, Memo)>> {
+        let indexes = self.indexes(account);
+        let selected = match number {
+            Some(number) => vec![(number, memo_index(&indexes, number)?)],
+            None => indexes
+                .iter()
+                .enumerate()
+                .filter(|(_, index)| !self.memos[**index].read)
+                .map(|(position, index)| (position + 1, *index))
+                .collect(),
+        };
+        let mut memos = Vec::new();
+        for (number, index) in selected {
+            self.memos[index].read = true;
+            memos.push((number, self.memos[index].clone()));
+        }
+        if !memos.is_empty() {
+            self.save()?;
+        }
+        Ok(memos)
+    }
+    /// Deletes the memo with the given number, or every memo when `number` is `None`.
+    /// Returns how many memos were deleted.
+    pub fn delete(&mut self, account: &str, number: Option<usize>) -> Result<usize> {
+        let indexes = self.indexes(account);
+        let deleted = match number {
+            Some(number) => {
+                let index = memo_index(&indexes, number)?;
+                self.memos.remove(index);
+                1
+            }
+            None => {
+                self.memos
+                    .retain(|memo| !memo.to.eq_ignore_ascii_case(account));
+                indexes.len()
+            }
+        };
+        if deleted > 0 {
+            self.save()?;
+        }
+        Ok(deleted)
+    }
+    /// Positions in the store of the memos of the account, oldest first.
+    fn indexes(&self, account: &str) -> Vec<usize> {
+        self.memos
+            .iter()
+            .enumerate()
+            .filter(|(_, memo)| memo.to.eq_ignore_ascii_case(account))
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+/// Position in the store of the memo with the given number.
+fn memo_index(indexes: &[usize], number: usize) -> Result<usize> {
+    number
+        .checked_sub(1)
+        .and_then(|position| indexes.get(position))
+        .copied()
+        .with_context(|| format!("there is no memo number {}", number))
+}
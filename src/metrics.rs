@@ -0,0 +1,274 @@
+use anyhow::*;
+use tiny_http::{Header, Method, Response, Server};
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crate::defs::ServerEngine;
+
+/// Upper bounds in seconds of the `formatted_terminal` render time buckets.
+const RENDER_BUCKETS: [f64; 8] = [0.00001, 0.00005, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations per bucket of `RENDER_BUCKETS`, not cumulative.
+    buckets: [u64; RENDER_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// Counters updated by the sessions and the service bus, gauges are read at scrape time.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    /// Channel name to messages published.
+    messages: Mutex<BTreeMap<String, u64>>,
+    /// Command name to times it was run.
+    commands: Mutex<BTreeMap<String, u64>>,
+    /// Rejection reason to connections turned away.
+    rejections: Mutex<BTreeMap<String, u64>>,
+    render: Mutex<Histogram>,
+}
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add_bytes_received(&self, bytes: usize) {
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    pub fn add_bytes_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    pub fn count_message(&self, channel: &str) {
+        *self
+            .messages
+            .lock()
+            .unwrap()
+            .entry(channel.to_string())
+            .or_default() += 1;
+    }
+    pub fn count_command(&self, command: &str) {
+        *self
+            .commands
+            .lock()
+            .unwrap()
+            .entry(command.to_string())
+            .or_default() += 1;
+    }
+    pub fn count_rejection(&self, reason: &str) {
+        *self
+            .rejections
+            .lock()
+            .unwrap()
+            .entry(reason.to_string())
+            .or_default() += 1;
+    }
+    pub fn observe_render(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let mut render = self.render.lock().unwrap();
+        if let Some(bucket) = RENDER_BUCKETS.iter().position(|le| seconds <= *le) {
+            render.buckets[bucket] += 1;
+        }
+        render.count += 1;
+        render.sum += seconds;
+    }
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self, server: &ServerEngine) -> String {
+        let mut out = String::new();
+        let clients = server.clients.lock().unwrap().list.clone();
+        let channels = server.channels.lock().unwrap().list.clone();
+
+        header(&mut out, "chathole_clients", "gauge", "Connected clients.");
+        let _ = writeln!(out, "chathole_clients {}", clients.len());
+        header(&mut out, "chathole_channels", "gauge", "Open channels.");
+        let _ = writeln!(out, "chathole_channels {}", channels.len());
+        header(
+            &mut out,
+            "chathole_channel_users",
+            "gauge",
+            "Clients subscribed to the channel.",
+        );
+        let mut names = Vec::new();
+        for channel in channels.iter() {
+            let channel = channel.lock().unwrap();
+            names.push(channel.name.clone());
+            let _ = writeln!(
+                out,
+                "chathole_channel_users{{channel=\"{}\"}} {}",
+                escape(&channel.name),
+                channel.subscribers.len()
+            );
+        }
+
+        header(
+            &mut out,
+            "chathole_messages_total",
+            "counter",
+            "Messages published to the channel.",
+        );
+        let mut messages = self.messages.lock().unwrap();
+        // removed channels would otherwise be kept and exported forever
+        messages.retain(|name, _| names.contains(name));
+        for (channel, count) in messages.iter() {
+            let _ = writeln!(
+                out,
+                "chathole_messages_total{{channel=\"{}\"}} {}",
+                escape(channel),
+                count
+            );
+        }
+        drop(messages);
+        header(
+            &mut out,
+            "chathole_commands_total",
+            "counter",
+            "Commands run by clients.",
+        );
+        for (command, count) in self.commands.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "chathole_commands_total{{command=\"{}\"}} {}",
+                escape(command),
+                count
+            );
+        }
+        header(
+            &mut out,
+            "chathole_rejected_connections_total",
+            "counter",
+            "Connections turned away before a client was created.",
+        );
+        for (reason, count) in self.rejections.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "chathole_rejected_connections_total{{reason=\"{}\"}} {}",
+                reason, count
+            );
+        }
+        header(
+            &mut out,
+            "chathole_received_bytes_total",
+            "counter",
+            "Bytes read from client connections.",
+        );
+        let _ = writeln!(
+            out,
+            "chathole_received_bytes_total {}",
+            self.bytes_received.load(Ordering::Relaxed)
+        );
+        header(
+            &mut out,
+            "chathole_sent_bytes_total",
+            "counter",
+            "Bytes written to client connections.",
+        );
+        let _ = writeln!(
+            out,
+            "chathole_sent_bytes_total {}",
+            self.bytes_sent.load(Ordering::Relaxed)
+        );
+
+        header(
+            &mut out,
+            "chathole_queue_depth",
+            "gauge",
+            "Messages waiting in the service bus, or in all the client queues.",
+        );
+        let _ = writeln!(
+            out,
+            "chathole_queue_depth{{bus=\"service\"}} {}",
+            server.service_bus.lock().unwrap().len()
+        );
+        // one series per client would grow with every connection, only the totals are kept
+        let depths = clients
+            .iter()
+            .map(|client| client.lock().unwrap().rx.len())
+            .collect::<Vec<usize>>();
+        let _ = writeln!(
+            out,
+            "chathole_queue_depth{{bus=\"clients\"}} {}",
+            depths.iter().sum::<usize>()
+        );
+        header(
+            &mut out,
+            "chathole_client_queue_max_depth",
+            "gauge",
+            "Messages waiting in the fullest client queue.",
+        );
+        let _ = writeln!(
+            out,
+            "chathole_client_queue_max_depth {}",
+            depths.iter().max().unwrap_or(&0)
+        );
+
+        header(
+            &mut out,
+            "chathole_render_seconds",
+            "histogram",
+            "Time spent rendering the chat terminal.",
+        );
+        let render = self.render.lock().unwrap();
+        let mut cumulative = 0;
+        for (le, count) in RENDER_BUCKETS.iter().zip(render.buckets.iter()) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "chathole_render_seconds_bucket{{le=\"{}\"}} {}",
+                le, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "chathole_render_seconds_bucket{{le=\"+Inf\"}} {}",
+            render.count
+        );
+        let _ = writeln!(out, "chathole_render_seconds_sum {}", render.sum);
+        let _ = writeln!(out, "chathole_render_seconds_count {}", render.count);
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escapes a label value as required by the exposition format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serves `GET /metrics` on the configured address until the process exits.
+pub fn handle_metrics(server: ServerEngine) -> Result<()> {
+    let bind = server.config.lock().unwrap().metrics.bind.clone();
+    let http = Server::http(&bind)
+        .map_err(|e| anyhow!("unable to bind metrics listener {}: {}", bind, e))?;
+    log::info!("metrics listening on {}", bind);
+    for request in http.incoming_requests() {
+        let response = match (request.method(), request.url()) {
+            (Method::Get, "/metrics") => Response::from_string(server.metrics.render(&server))
+                .with_header(
+                    "Content-Type: text/plain; version=0.0.4"
+                        .parse::<Header>()
+                        .unwrap(),
+                ),
+            _ => Response::from_string("not found").with_status_code(404),
+        };
+        if let Err(e) = request.respond(response) {
+            log::warn!("unable to send metrics: {}", e);
+        }
+    }
+    Ok(())
+}
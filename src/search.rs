@@ -0,0 +1,292 @@
+use anyhow::*;
+use chrono::{Local, NaiveDate};
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::{defs::RenderStyle, storage::LogRecord};
+
+/// Parsed `/search [#chan] [from:nick] [since:date] [page:n] <terms>` arguments.
+#[derive(Debug, Clone, Default)]
+pub struct SearchQuery {
+    pub channel: Option<String>,
+    pub from: Option<String>,
+    pub since: Option<NaiveDate>,
+    pub page: usize,
+    pub terms: Vec<String>,
+}
+impl SearchQuery {
+    pub fn parse(args: &str) -> Result<Self> {
+        let mut query = SearchQuery {
+            page: 1,
+            ..Default::default()
+        };
+        for word in args.split_whitespace() {
+            if word.starts_with('#') {
+                query.channel = Some(word.to_string());
+            } else if let Some(nick) = word.strip_prefix("from:") {
+                query.from = Some(nick.to_string());
+            } else if let Some(date) = word.strip_prefix("since:") {
+                let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .with_context(|| format!("invalid date {}, expected YYYY-MM-DD", date))?;
+                query.since = Some(date);
+            } else if let Some(page) = word.strip_prefix("page:") {
+                query.page = page
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|page| *page > 0)
+                    .with_context(|| format!("invalid page {}", page))?;
+            } else {
+                query.terms.extend(tokenize(word));
+            }
+        }
+        if query.terms.is_empty() && query.from.is_none() {
+            bail!("at least one search term or from:nick is required");
+        }
+        Ok(query)
+    }
+    fn matches(&self, record: &LogRecord) -> bool {
+        if let Some(channel) = &self.channel {
+            // like /notice, `#rust` names both the `#rust` and the `rust` channel
+            let name = &channel[1..];
+            if !record.target.eq_ignore_ascii_case(channel)
+                && !record.target.eq_ignore_ascii_case(name)
+            {
+                return false;
+            }
+        }
+        if let Some(from) = &self.from {
+            if !record.nick.eq_ignore_ascii_case(from) {
+                return false;
+            }
+        }
+        if let Some(since) = self.since {
+            if record.timestamp.date_naive() < since {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// One page of search results, newest first.
+#[derive(Debug, Clone)]
+pub struct SearchPage {
+    pub records: Vec<LogRecord>,
+    pub page: usize,
+    pub pages: usize,
+    pub total: usize,
+}
+
+/// Inverted index over the retained channel history.
+#[derive(Debug, Clone)]
+pub struct SearchIndex {
+    records: VecDeque<LogRecord>,
+    /// Id of the record at the front of `records`.
+    first_id: usize,
+    /// Term to ascending record ids.
+    terms: HashMap<String, VecDeque<usize>>,
+    max_records: usize,
+}
+impl SearchIndex {
+    pub fn new(max_records: usize) -> Self {
+        SearchIndex {
+            records: VecDeque::new(),
+            first_id: 0,
+            terms: HashMap::new(),
+            max_records,
+        }
+    }
+    pub fn add(&mut self, record: LogRecord) {
+        let id = self.first_id + self.records.len();
+        let mut terms = tokenize(&record.payload);
+        terms.sort();
+        terms.dedup();
+        for term in terms {
+            self.terms.entry(term).or_default().push_back(id);
+        }
+        self.records.push_back(record);
+        while self.records.len() > self.max_records {
+            self.evict();
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+    pub fn search(&self, query: &SearchQuery, page_size: usize) -> SearchPage {
+        let ids: Vec<usize> = match query.terms.is_empty() {
+            true => (self.first_id..self.first_id + self.records.len()).collect(),
+            false => self.intersect(&query.terms),
+        };
+        let matches = ids
+            .into_iter()
+            .rev()
+            .map(|id| &self.records[id - self.first_id])
+            .filter(|record| query.matches(record))
+            .collect::<Vec<&LogRecord>>();
+        let total = matches.len();
+        let page_size = page_size.max(1);
+        let pages = total.div_ceil(page_size).max(1);
+        let page = query.page.min(pages);
+        let records = matches
+            .into_iter()
+            .skip((page - 1) * page_size)
+            .take(page_size)
+            .cloned()
+            .collect();
+        SearchPage {
+            records,
+            page,
+            pages,
+            total,
+        }
+    }
+    fn intersect(&self, terms: &[String]) -> Vec<usize> {
+        let mut postings = Vec::new();
+        for term in terms {
+            match self.terms.get(term) {
+                Some(ids) => postings.push(ids),
+                None => return Vec::new(),
+            }
+        }
+        postings.sort_by_key(|ids| ids.len());
+        let (shortest, rest) = postings.split_first().unwrap();
+        shortest
+            .iter()
+            .filter(|id| rest.iter().all(|ids| ids.binary_search(id).is_ok()))
+            .cloned()
+            .collect()
+    }
+    fn evict(&mut self) {
+        let Some(record) = self.records.pop_front() else {
+            return;
+        };
+        for term in tokenize(&record.payload) {
+            if let Some(ids) = self.terms.get_mut(&term) {
+                while ids.front().is_some_and(|id| *id <= self.first_id) {
+                    ids.pop_front();
+                }
+                if ids.is_empty() {
+                    self.terms.remove(&term);
+                }
+            }
+        }
+        self.first_id += 1;
+    }
+}
+
+/// Lowercase alphanumeric words, so `https://example.com/x` matches `example`.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+pub fn format_record(record: &LogRecord) -> String {
+    let (nick, payload) = record.to_message().render(RenderStyle::Plain);
+    format!(
+        "{} {} {}{}",
+        record
+            .timestamp
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M"),
+        record.target,
+        nick,
+        payload
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::defs::MessageKind;
+
+    fn record(target: &str, nick: &str, payload: &str) -> LogRecord {
+        LogRecord {
+            timestamp: Local::now(),
+            target: target.to_string(),
+            nick: nick.to_string(),
+            payload: payload.to_string(),
+            kind: MessageKind::Chat,
+        }
+    }
+
+    fn payloads(index: &SearchIndex, query: &str) -> Vec<String> {
+        let query = SearchQuery::parse(query).unwrap();
+        index
+            .search(&query, 100)
+            .records
+            .into_iter()
+            .map(|record| record.payload)
+            .collect()
+    }
+
+    #[test]
+    fn intersects_terms_newest_first() {
+        let mut index = SearchIndex::new(10);
+        index.add(record("#rust", "alice", "the borrow checker"));
+        index.add(record("#rust", "bob", "Borrow, borrow and borrow"));
+        index.add(record("#rust", "alice", "checker of the borrow kind"));
+        index.add(record("#rust", "bob", "nothing here"));
+        assert_eq!(
+            payloads(&index, "borrow checker"),
+            ["checker of the borrow kind", "the borrow checker"]
+        );
+        assert_eq!(payloads(&index, "BORROW").len(), 3);
+        assert!(payloads(&index, "borrow missing").is_empty());
+    }
+
+    #[test]
+    fn evicts_the_oldest_records() {
+        let mut index = SearchIndex::new(2);
+        index.add(record("#rust", "alice", "first shared"));
+        index.add(record("#rust", "alice", "second shared"));
+        index.add(record("#rust", "alice", "third shared"));
+        assert_eq!(index.len(), 2);
+        assert!(!index.terms.contains_key("first"));
+        assert_eq!(index.terms["shared"], [1, 2]);
+        assert!(payloads(&index, "first").is_empty());
+        assert_eq!(
+            payloads(&index, "shared"),
+            ["third shared", "second shared"]
+        );
+        index.add(record("#rust", "alice", "fourth"));
+        assert_eq!(index.terms["shared"], [2]);
+        assert_eq!(payloads(&index, "third shared"), ["third shared"]);
+    }
+
+    #[test]
+    fn filters_and_pages() {
+        let mut index = SearchIndex::new(10);
+        for i in 0..5 {
+            index.add(record("#rust", "alice", &format!("rust {}", i)));
+        }
+        index.add(record("go", "bob", "rust in go"));
+        assert_eq!(payloads(&index, "from:BOB rust"), ["rust in go"]);
+        assert_eq!(payloads(&index, "#go rust"), ["rust in go"]);
+        assert_eq!(payloads(&index, "#rust rust").len(), 5);
+        assert_eq!(payloads(&index, "from:alice").len(), 5);
+        let query = SearchQuery::parse("#rust rust page:2").unwrap();
+        let page = index.search(&query, 2);
+        assert_eq!((page.page, page.pages, page.total), (2, 3, 5));
+        assert_eq!(page.records[0].payload, "rust 2");
+        // pages past the end show the last one
+        let query = SearchQuery::parse("rust page:9").unwrap();
+        assert_eq!(index.search(&query, 2).page, 3);
+    }
+
+    #[test]
+    fn parses_queries() {
+        let query = SearchQuery::parse("#rust from:alice since:2024-01-31 Some-Terms").unwrap();
+        assert_eq!(query.channel.as_deref(), Some("#rust"));
+        assert_eq!(query.from.as_deref(), Some("alice"));
+        assert_eq!(query.since, NaiveDate::from_ymd_opt(2024, 1, 31));
+        assert_eq!(query.terms, ["some", "terms"]);
+        assert!(SearchQuery::parse("#rust").is_err());
+        assert!(SearchQuery::parse("since:2024-13-01 rust").is_err());
+        assert!(SearchQuery::parse("page:0 rust").is_err());
+    }
+}
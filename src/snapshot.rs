@@ -0,0 +1,196 @@
+use anyhow::*;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use std::{collections::BTreeSet, path::Path};
+
+use crate::accounts::Account;
+
+/// Bumped whenever the snapshot layout changes, older files are upgraded by `migrate`.
+pub const SNAPSHOT_VERSION: u64 = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u64,
+    pub saved_at: DateTime<Local>,
+    pub channels: Vec<ChannelSnapshot>,
+    pub accounts: Vec<Account>,
+}
+impl Snapshot {
+    pub fn new(channels: Vec<ChannelSnapshot>, accounts: Vec<Account>) -> Self {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            saved_at: Local::now(),
+            channels,
+            accounts,
+        }
+    }
+    /// Loads the snapshot at `path`, returns `None` if there is none yet.
+    pub fn load(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read snapshot {}", path.display()))?;
+        let value: serde_json::Value = serde_json::from_str(&content)
+            .with_context(|| format!("unable to parse snapshot {}", path.display()))?;
+        let snapshot = migrate(value)
+            .with_context(|| format!("unable to load snapshot {}", path.display()))?;
+        Ok(Some(snapshot))
+    }
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("unable to write snapshot {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelSnapshot {
+    pub name: String,
+    pub topic: Option<String>,
+    /// Lowercase accounts of the channel operators.
+    pub ops: BTreeSet<String>,
+    pub bans: BTreeSet<String>,
+    pub slow_mode: Option<u64>,
+}
+
+/// Upgrades a snapshot of any known version to the current layout one version at a time.
+fn migrate(mut value: serde_json::Value) -> Result<Snapshot> {
+    let mut version = value
+        .get("version")
+        .and_then(|version| version.as_u64())
+        .context("snapshot version is missing")?;
+    if version > SNAPSHOT_VERSION {
+        bail!(
+            "snapshot version {} is newer than the supported version {}",
+            version,
+            SNAPSHOT_VERSION
+        );
+    }
+    while version < SNAPSHOT_VERSION {
+        value = migrate_step(version, value)?;
+        version += 1;
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Turns a snapshot of `version` into one of `version + 1`, every layout change adds a step here.
+fn migrate_step(version: u64, mut value: serde_json::Value) -> Result<serde_json::Value> {
+    match version {
+        1 => {
+            // version 1 kept unused modes, the persistent flag now only comes from the config,
+            // and operators were nicks: only those matching a registered account are kept
+            let accounts = value
+                .get("accounts")
+                .and_then(|accounts| accounts.as_array())
+                .map(|accounts| {
+                    accounts
+                        .iter()
+                        .filter_map(|account| account.get("nick")?.as_str())
+                        .map(|nick| nick.to_lowercase())
+                        .collect::<BTreeSet<String>>()
+                })
+                .unwrap_or_default();
+            let channels = value
+                .get_mut("channels")
+                .and_then(|channels| channels.as_array_mut())
+                .context("snapshot channels are missing")?;
+            for channel in channels.iter_mut() {
+                let Some(channel) = channel.as_object_mut() else {
+                    continue;
+                };
+                channel.remove("modes");
+                channel.remove("persistent");
+                if let Some(ops) = channel.get_mut("ops").and_then(|ops| ops.as_array_mut()) {
+                    ops.retain(|op| op.as_str().is_some_and(|op| accounts.contains(op)));
+                }
+            }
+            value["version"] = 2.into();
+            Ok(value)
+        }
+        _ => bail!("no migration available from snapshot version {}", version),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn account(nick: &str) -> serde_json::Value {
+        json!({ "nick": nick, "password_hash": "", "created_at": 0 })
+    }
+
+    #[test]
+    fn migrates_version_1() {
+        let v1 = json!({
+            "version": 1,
+            "saved_at": "2024-01-01T00:00:00+00:00",
+            "channels": [{
+                "name": "#rust",
+                "topic": "borrowck",
+                "ops": ["alice", "mallory"],
+                "bans": ["eve"],
+                "modes": ["m"],
+                "persistent": true,
+                "slow_mode": 30,
+            }],
+            "accounts": [account("Alice")],
+        });
+        let snapshot = migrate(v1).unwrap();
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        let channel = &snapshot.channels[0];
+        assert_eq!(channel.name, "#rust");
+        assert_eq!(channel.topic.as_deref(), Some("borrowck"));
+        // mallory never registered, anyone could take that nick
+        assert_eq!(channel.ops, BTreeSet::from(["alice".to_string()]));
+        assert_eq!(channel.bans, BTreeSet::from(["eve".to_string()]));
+        assert_eq!(channel.slow_mode, Some(30));
+        assert_eq!(snapshot.accounts[0].nick, "Alice");
+    }
+
+    #[test]
+    fn migration_drops_removed_fields() {
+        let v1 = json!({
+            "version": 1,
+            "saved_at": "2024-01-01T00:00:00+00:00",
+            "channels": [{ "name": "#go", "ops": ["bob"], "modes": [], "persistent": false }],
+            "accounts": [],
+        });
+        let value = migrate_step(1, v1).unwrap();
+        assert_eq!(value["version"], 2);
+        let channel = value["channels"][0].as_object().unwrap();
+        assert!(!channel.contains_key("modes"));
+        assert!(!channel.contains_key("persistent"));
+        assert_eq!(channel["ops"], json!([]));
+    }
+
+    #[test]
+    fn loads_the_current_version_as_is() {
+        let snapshot = Snapshot::new(
+            vec![ChannelSnapshot {
+                name: "#rust".to_string(),
+                ops: BTreeSet::from(["alice".to_string()]),
+                ..Default::default()
+            }],
+            Vec::new(),
+        );
+        let migrated = migrate(serde_json::to_value(&snapshot).unwrap()).unwrap();
+        assert_eq!(migrated.channels[0].ops, snapshot.channels[0].ops);
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let newer = json!({ "version": SNAPSHOT_VERSION + 1, "channels": [], "accounts": [] });
+        assert!(migrate(newer).is_err());
+        assert!(migrate(json!({ "channels": [], "accounts": [] })).is_err());
+        assert!(migrate(json!({ "version": 1, "accounts": [] })).is_err());
+        assert!(migrate_step(0, json!({})).is_err());
+    }
+}
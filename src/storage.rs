@@ -0,0 +1,239 @@
+use anyhow::*;
+use chrono::{DateTime, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::defs::{ChatMessage, Event, MessageKind};
+
+/// One line of a chat log file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogRecord {
+    pub timestamp: DateTime<Local>,
+    /// Channel name, or the two nicks of a private conversation.
+    pub target: String,
+    pub nick: String,
+    pub payload: String,
+    #[serde(default)]
+    pub kind: MessageKind,
+}
+impl LogRecord {
+    /// Record of a chat line, action or notice, other events are not logged.
+    pub fn from_message(target: &str, msg: &ChatMessage) -> Option<Self> {
+        let Event::Message {
+            kind, nick, text, ..
+        } = &msg.event
+        else {
+            return None;
+        };
+        Some(LogRecord {
+            timestamp: msg.timestamp,
+            target: target.to_string(),
+            nick: nick.clone(),
+            payload: text.clone(),
+            kind: *kind,
+        })
+    }
+    pub fn to_message(&self) -> ChatMessage {
+        let mut msg = ChatMessage::message(self.kind, self.nick.clone(), self.payload.clone());
+        msg.timestamp = self.timestamp;
+        msg
+    }
+}
+
+/// Log target of a private conversation, the same for both directions.
+pub fn private_target(from: &str, to: &str) -> String {
+    let mut nicks = [from.to_lowercase(), to.to_lowercase()];
+    nicks.sort();
+    format!("@{}+{}", nicks[0], nicks[1])
+}
+
+/// Log files kept open for appending, the least recently written one is closed past this.
+const MAX_OPEN_FILES: usize = 64;
+
+/// Append only JSONL chat logs, one directory per target and one file per day.
+#[derive(Debug)]
+pub struct ChatStore {
+    path: PathBuf,
+    /// Open log of each target with its day and the `writes` count at its last write.
+    files: HashMap<String, (NaiveDate, File, u64)>,
+    writes: u64,
+}
+impl ChatStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        std::fs::create_dir_all(&path)
+            .with_context(|| format!("unable to create chat log dir {}", path.display()))?;
+        Ok(ChatStore {
+            path,
+            files: HashMap::new(),
+            writes: 0,
+        })
+    }
+    pub fn append(&mut self, record: &LogRecord) -> Result<()> {
+        let date = record.timestamp.date_naive();
+        let rotate = match self.files.get(&record.target) {
+            Some((file_date, _, _)) => *file_date != date,
+            None => true,
+        };
+        if rotate && !self.files.contains_key(&record.target) {
+            self.close_least_recent()?;
+        }
+        if rotate {
+            let dir = self.target_dir(&record.target);
+            std::fs::create_dir_all(&dir)?;
+            let file_path = dir.join(format!("{}.jsonl", date.format("%Y-%m-%d")));
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&file_path)
+                .with_context(|| format!("unable to open chat log {}", file_path.display()))?;
+            self.files.insert(record.target.clone(), (date, file, 0));
+        }
+        self.writes += 1;
+        let (_, file, last_write) = self.files.get_mut(&record.target).unwrap();
+        *last_write = self.writes;
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+    pub fn flush(&mut self) -> Result<()> {
+        for (_, file, _) in self.files.values_mut() {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+    /// Closes the least recently written log once `MAX_OPEN_FILES` are open, so targets that
+    /// are gone, like removed channels and old private conversations, do not keep theirs.
+    fn close_least_recent(&mut self) -> Result<()> {
+        if self.files.len() < MAX_OPEN_FILES {
+            return Ok(());
+        }
+        let target = self
+            .files
+            .iter()
+            .min_by_key(|(_, (_, _, last_write))| *last_write)
+            .map(|(target, _)| target.clone());
+        if let Some((_, file, _)) = target.and_then(|target| self.files.remove(&target)) {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+    /// Returns up to `limit` of the most recent records of the target, oldest first.
+    pub fn load_recent(&self, target: &str, limit: usize) -> Result<Vec<LogRecord>> {
+        let mut records = Vec::new();
+        for file_path in self.log_files(target)?.iter().rev() {
+            let mut day = read_records(file_path)?;
+            day.append(&mut records);
+            records = day;
+            if records.len() >= limit {
+                break;
+            }
+        }
+        let skip = records.len().saturating_sub(limit);
+        Ok(records.split_off(skip))
+    }
+    /// Returns every record of the target logged on or after `since`, oldest first.
+    pub fn load_since(&self, target: &str, since: NaiveDate) -> Result<Vec<LogRecord>> {
+        let mut records = Vec::new();
+        for file_path in self.log_files(target)? {
+            let date = file_path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| NaiveDate::parse_from_str(stem, "%Y-%m-%d").ok());
+            if date.is_some_and(|date| date >= since) {
+                records.extend(read_records(&file_path)?);
+            }
+        }
+        Ok(records)
+    }
+    /// Returns the directory name of every logged target.
+    pub fn targets(&self) -> Result<Vec<String>> {
+        let mut targets = Vec::new();
+        for entry in std::fs::read_dir(&self.path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                targets.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        targets.sort();
+        Ok(targets)
+    }
+    fn target_dir(&self, target: &str) -> PathBuf {
+        self.path.join(sanitize(target))
+    }
+    fn log_files(&self, target: &str) -> Result<Vec<PathBuf>> {
+        let dir = self.target_dir(target);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut files = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+            .collect::<Vec<PathBuf>>();
+        files.sort();
+        Ok(files)
+    }
+}
+
+fn read_records(path: &Path) -> Result<Vec<LogRecord>> {
+    let file = File::open(path)?;
+    let records = BufReader::new(file)
+        .lines()
+        .map_while(|line| line.ok())
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .collect();
+    Ok(records)
+}
+
+/// Keeps user chosen channel names from escaping the log directory.
+fn sanitize(target: &str) -> String {
+    target
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || "-_#@+".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closes_the_least_recently_written_logs() {
+        let path = std::env::temp_dir().join(format!("chathole-storage-{}", std::process::id()));
+        let mut store = ChatStore::open(&path).unwrap();
+        let record = |target: &str| LogRecord {
+            timestamp: Local::now(),
+            target: target.to_string(),
+            nick: "alice".to_string(),
+            payload: "hello".to_string(),
+            kind: MessageKind::Chat,
+        };
+        for i in 0..MAX_OPEN_FILES {
+            store.append(&record(&format!("#{}", i))).unwrap();
+        }
+        store.append(&record("#0")).unwrap();
+        store.append(&record("#new")).unwrap();
+        assert_eq!(store.files.len(), MAX_OPEN_FILES);
+        assert!(store.files.contains_key("#0"));
+        assert!(!store.files.contains_key("#1"));
+        // a closed log is opened again on the next write
+        store.append(&record("#1")).unwrap();
+        assert_eq!(store.load_recent("#1", 10).unwrap().len(), 2);
+        assert_eq!(store.files.len(), MAX_OPEN_FILES);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
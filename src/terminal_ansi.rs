@@ -13,6 +13,9 @@ impl AnsiControlCodes {
     pub fn get_cursor_position(&self) -> String {
         String::from("\x1B[6n")
     }
+    pub fn bell(&self) -> String {
+        String::from("\x07")
+    }
     pub fn clear_screen(&self) -> String {
         String::from("\x1B[2J")
     }
@@ -153,3 +156,21 @@ pub fn init_terminal() -> String {
     ]
     .concat()
 }
+
+/// Removes the escape sequences from a colored text.
+pub fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        for c in chars.by_ref() {
+            if c.is_ascii_alphabetic() {
+                break;
+            }
+        }
+    }
+    out
+}